@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Parser;
+
+use ai::evolution::{Checkpoint, Trainer, TrainerConfig};
+use rust_driving_game_core::default_tracks::{load_track_or_preset, make_track};
+
+#[derive(Parser)]
+#[command(about = "Evolves neural network drivers for a track")]
+struct Cli {
+    #[arg(long, help = "Track file or built-in track name to train on, the straight sprint if not given")]
+    track: Option<PathBuf>,
+    #[arg(long, default_value_t = 50, help = "Train until this many generations have been scored")]
+    generations: u32,
+    #[arg(long, help = "64 if not given, must match the checkpoint when resuming")]
+    population: Option<usize>,
+    #[arg(long, value_delimiter = ',', help = "8 if not given, must match the checkpoint when resuming")]
+    hidden: Option<Vec<usize>>,
+    #[arg(long, help = "0 if not given, must match the checkpoint when resuming")]
+    seed: Option<u64>,
+    #[arg(long, help = "Defaults to one per CPU")]
+    threads: Option<usize>,
+    #[arg(long, help = "Saved after every generation, and resumed from if it already exists")]
+    checkpoint: Option<PathBuf>,
+    #[arg(long, default_value = "best-driver.json", help = "Where the best driver found is saved")]
+    out: PathBuf,
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    exit(1);
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let track = match &cli.track {
+        Some(path) => {
+            load_track_or_preset(path).unwrap_or_else(|e| fail(format!("Failed to load track {}: {}", path.display(), e)))
+        }
+        None => make_track(),
+    };
+    // Better to stop now than after hours of training on a track nobody can finish
+    let validation = track.validate();
+    eprint!("{}", validation);
+    if !validation.is_valid() {
+        fail("The track has errors, fix them before training on it".to_string());
+    }
+    let mut trainer = match &cli.checkpoint {
+        Some(path) if path.exists() => {
+            let checkpoint = Checkpoint::load(path)
+                .unwrap_or_else(|e| fail(format!("Failed to load checkpoint {}: {}", path.display(), e)));
+            checkpoint
+                .check_settings(cli.population, cli.hidden.as_deref(), cli.seed)
+                .and_then(|_| {
+                    println!("Resuming from generation {} of {}", checkpoint.generation, path.display());
+                    Trainer::resume(&track, checkpoint)
+                })
+                .unwrap_or_else(|e| fail(format!("Can't resume from {}: {}", path.display(), e)))
+        }
+        _ => {
+            let defaults = TrainerConfig::default();
+            let config = TrainerConfig {
+                population: cli.population.unwrap_or(defaults.population),
+                hidden_layers: cli.hidden.clone().unwrap_or(defaults.hidden_layers.clone()),
+                seed: cli.seed.unwrap_or(defaults.seed),
+                ..defaults
+            };
+            Trainer::new(&track, config)
+        }
+    };
+    if let Some(threads) = cli.threads {
+        trainer.threads = threads;
+    }
+
+    while trainer.checkpoint.generation < cli.generations {
+        println!("{}", trainer.step());
+        if let Some(path) = &cli.checkpoint {
+            trainer
+                .checkpoint
+                .save(path)
+                .unwrap_or_else(|e| fail(format!("Failed to save checkpoint {}: {}", path.display(), e)));
+        }
+    }
+
+    let Some(best) = &trainer.checkpoint.best else {
+        fail("No generations were scored, so there is no driver to save".to_string());
+    };
+    let driver = trainer.checkpoint.config.driver(best.weights.clone());
+    let contents = serde_json::to_string_pretty(&driver).unwrap_or_else(|e| fail(format!("Failed to write driver: {}", e)));
+    std::fs::write(&cli.out, contents).unwrap_or_else(|e| fail(format!("Failed to save driver {}: {}", cli.out.display(), e)));
+    println!("Best fitness {:.2}, saved to {}", best.fitness, cli.out.display());
+}
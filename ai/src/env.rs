@@ -0,0 +1,352 @@
+use serde::{Deserialize, Serialize};
+
+use rust_driving_game_core::car::{Car, CarState, PhysicsConstants};
+use rust_driving_game_core::car_progress::CarProgress;
+use rust_driving_game_core::coordinates::Vec2d;
+use rust_driving_game_core::gameloop::TIME_PER_TICK;
+use rust_driving_game_core::input::AnalogInput;
+use rust_driving_game_core::sensors::RaySensor;
+use rust_driving_game_core::track::{GridSlot, Track};
+
+pub type Action = AnalogInput;
+
+// What goes into each observation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ObservationConfig {
+    // Position and heading, the heading as its sine and cosine so it doesn't jump at +-pi
+    pub pose: bool,
+    // Forward and sideways speed
+    pub velocity: bool,
+    // Distances to the walls around the car
+    pub rays: RaySensor,
+}
+
+impl ObservationConfig {
+    // Number of values in a flattened observation
+    pub fn len(&self) -> usize {
+        4 * self.pose as usize + 2 * self.velocity as usize + self.rays.angles_radians.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn observe(&self, track: &Track, car: &Car) -> Observation {
+        Observation {
+            pose: self.pose.then(|| {
+                let (sin, cos) = car.direction_radians.sin_cos();
+                [car.pos.x, car.pos.y, sin, cos]
+            }),
+            velocity: self.velocity.then_some([car.velocity, car.lateral_velocity]),
+            rays: self.rays.distances(track, car.pos, car.direction_radians),
+        }
+    }
+}
+
+impl Default for ObservationConfig {
+    fn default() -> Self {
+        ObservationConfig {
+            pose: true,
+            velocity: true,
+            rays: RaySensor::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RewardConfig {
+    // Per metre gained along the racing line, so going backwards costs as much
+    pub progress_per_m: f32,
+    pub time_penalty_per_s: f32,
+    pub crash_penalty: f32,
+    pub finish_bonus: f32,
+}
+
+impl RewardConfig {
+    // Everything `Env::step` would have handed out over a whole run that ended in `progress`
+    pub fn total(&self, progress: &CarProgress, time_per_tick_s: f32) -> f32 {
+        let ticks = progress.end_tick.unwrap_or(progress.ticks);
+        let mut total = self.progress_per_m * progress.distance_m - self.time_penalty_per_s * ticks as f32 * time_per_tick_s;
+        match progress.state {
+            CarState::Crashed => total -= self.crash_penalty,
+            CarState::Finished => total += self.finish_bonus,
+            _ => {}
+        }
+        total
+    }
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        RewardConfig {
+            progress_per_m: 1.0,
+            time_penalty_per_s: 1.0,
+            crash_penalty: 100.0,
+            finish_bonus: 100.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EnvConfig {
+    pub physics: PhysicsConstants,
+    pub time_per_tick_s: f32,
+    // Ticks each action is held for
+    pub action_repeat: u32,
+    pub observation: ObservationConfig,
+    pub reward: RewardConfig,
+    // Furthest `reset` moves the car sideways and turns it from the start line, picked from the seed
+    pub start_jitter_m: f32,
+    pub start_jitter_radians: f32,
+}
+
+impl EnvConfig {
+    // Where `Env::reset` puts the car for `seed`
+    pub fn start_slot(&self, track: &Track, seed: u64) -> GridSlot {
+        let mut rng = seed;
+        let sideways = unit_from_seed(&mut rng) * self.start_jitter_m;
+        let turn = unit_from_seed(&mut rng) * self.start_jitter_radians;
+        let start_right = Vec2d::from_heading(track.start_direction_radians()).perp_right();
+        GridSlot {
+            pos: track.start() + start_right * sideways,
+            direction_radians: track.start_direction_radians() + turn,
+        }
+    }
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        EnvConfig {
+            physics: PhysicsConstants::default(),
+            time_per_tick_s: TIME_PER_TICK,
+            action_repeat: 1,
+            observation: ObservationConfig::default(),
+            reward: RewardConfig::default(),
+            start_jitter_m: 0.0,
+            start_jitter_radians: 0.0,
+        }
+    }
+}
+
+// Parts left out by the `ObservationConfig` are None or empty
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Observation {
+    // x, y, then the sine and cosine of the heading
+    pub pose: Option<[f32; 4]>,
+    // Forward, then sideways to the right
+    pub velocity: Option<[f32; 2]>,
+    // Distance to the wall along each ray, capped at the ray length
+    pub rays: Vec<f32>,
+}
+
+impl Observation {
+    // Pose, velocity then rays, as fed to a network
+    pub fn to_vec(&self) -> Vec<f32> {
+        let mut values = Vec::with_capacity(6 + self.rays.len());
+        values.extend(self.pose.iter().flatten());
+        values.extend(self.velocity.iter().flatten());
+        values.extend(&self.rays);
+        values
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepInfo {
+    pub state: CarState,
+    pub tick: u64,
+    pub time_s: f32,
+    pub distance_m: f32,
+    pub completion: f32,
+    pub wall_hits: u32,
+}
+
+// A single car on a track, driven one action at a time
+pub struct Env<'a> {
+    pub track: &'a Track,
+    pub config: EnvConfig,
+    car: Car,
+    progress: CarProgress,
+    tick: u64,
+    time_s: f32,
+}
+
+impl<'a> Env<'a> {
+    pub fn new(track: &'a Track, config: EnvConfig) -> Env<'a> {
+        Env {
+            track,
+            config,
+            car: Car::on_start_line(track, "ai"),
+            progress: CarProgress::new(0.0),
+            tick: 0,
+            time_s: 0.0,
+        }
+    }
+
+    // Puts the car back on the start line. The seed only matters when there is start jitter
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        let slot = self.config.start_slot(self.track, seed);
+        self.car = Car::on_start_line(self.track, "ai");
+        self.car.reset(slot.pos, slot.direction_radians);
+        self.progress = CarProgress::new(0.0);
+        self.tick = 0;
+        self.time_s = 0.0;
+        self.observe()
+    }
+
+    // Holds the action for `action_repeat` ticks, or until the run ends. Once it has ended every
+    // step is done with no reward
+    pub fn step(&mut self, action: Action) -> (Observation, f32, bool, StepInfo) {
+        if self.is_done() {
+            return (self.observe(), 0.0, true, self.info());
+        }
+        let reward_config = self.config.reward;
+        let distance_before = self.progress.distance_m;
+        let mut elapsed_s = 0.0;
+        for _ in 0..self.config.action_repeat.max(1) {
+            let dt = self.config.time_per_tick_s;
+            self.car.update_position(&self.config.physics, dt, Some(action));
+            self.car.update_state(self.track, &mut self.progress, self.time_s, dt);
+            self.tick += 1;
+            self.time_s += dt;
+            elapsed_s += dt;
+            if self.is_done() {
+                break;
+            }
+        }
+        let mut reward = reward_config.progress_per_m * (self.progress.distance_m - distance_before)
+            - reward_config.time_penalty_per_s * elapsed_s;
+        match self.car.state {
+            CarState::Crashed => reward -= reward_config.crash_penalty,
+            CarState::Finished => reward += reward_config.finish_bonus,
+            _ => {}
+        }
+        (self.observe(), reward, self.is_done(), self.info())
+    }
+
+    pub fn observe(&self) -> Observation {
+        self.config.observation.observe(self.track, &self.car)
+    }
+
+    pub fn is_done(&self) -> bool {
+        !self.car.is_on_track()
+    }
+
+    pub fn car(&self) -> &Car {
+        &self.car
+    }
+
+    pub fn progress(&self) -> &CarProgress {
+        &self.progress
+    }
+
+    fn info(&self) -> StepInfo {
+        StepInfo {
+            state: self.car.state,
+            tick: self.tick,
+            time_s: self.time_s,
+            distance_m: self.progress.distance_m,
+            completion: self.progress.completion,
+            wall_hits: self.progress.wall_hits,
+        }
+    }
+}
+
+// splitmix64, mapped to -1..1, so neighbouring seeds still give unrelated starts
+fn unit_from_seed(state: &mut u64) -> f32 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32 * 2.0 - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use rust_driving_game_core::default_tracks::make_track;
+
+    use super::*;
+
+    const FLAT_OUT: Action = AnalogInput {
+        throttle: 1.0,
+        brake: 0.0,
+        steering: 0.0,
+    };
+
+    fn config() -> EnvConfig {
+        EnvConfig {
+            action_repeat: 4,
+            reward: RewardConfig {
+                progress_per_m: 2.0,
+                time_penalty_per_s: 3.0,
+                crash_penalty: 50.0,
+                finish_bonus: 70.0,
+            },
+            start_jitter_m: 2.0,
+            start_jitter_radians: 0.1,
+            ..EnvConfig::default()
+        }
+    }
+
+    // Drives flat out until the run ends, checking each reward against what the step covered, and
+    // returns how many steps were rewarded with the crash penalty and the finish bonus
+    fn drive_out(env: &mut Env) -> (usize, usize) {
+        let reward = env.config.reward;
+        let (mut crashes, mut finishes) = (0, 0);
+        env.reset(0);
+        let mut before = env.info();
+        loop {
+            let (_, got, done, info) = env.step(FLAT_OUT);
+            let earned = reward.progress_per_m * (info.distance_m - before.distance_m)
+                - reward.time_penalty_per_s * (info.time_s - before.time_s);
+            let extra = got - earned;
+            if (extra + reward.crash_penalty).abs() < 1e-3 {
+                crashes += 1;
+            } else if (extra - reward.finish_bonus).abs() < 1e-3 {
+                finishes += 1;
+            } else {
+                assert!(extra.abs() < 1e-3, "tick {} got {} for {}", info.tick, got, earned);
+            }
+            assert_eq!(done, info.state != CarState::Racing);
+            before = info;
+            if done {
+                break (crashes, finishes);
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_start() {
+        let track = make_track();
+        let mut env = Env::new(&track, config());
+        let first = env.reset(7);
+        env.step(FLAT_OUT);
+        assert_eq!(env.reset(7), first);
+        assert_ne!(env.reset(8), first);
+    }
+
+    #[test]
+    fn finishing_earns_the_bonus_once() {
+        let track = make_track();
+        let mut env = Env::new(&track, config());
+        assert_eq!(drive_out(&mut env), (0, 1));
+        assert_eq!(env.car().state, CarState::Finished);
+    }
+
+    #[test]
+    fn crashing_costs_the_penalty_once_and_then_nothing_happens() {
+        let mut track = make_track();
+        // Facing the right hand wall
+        track.set_start(Vec2d::new(0.0, 0.0), FRAC_PI_2);
+        let mut env = Env::new(&track, config());
+        assert_eq!(drive_out(&mut env), (1, 0));
+        assert_eq!(env.car().state, CarState::Crashed);
+
+        let (ended, info) = (env.observe(), env.info());
+        for _ in 0..3 {
+            assert_eq!(env.step(FLAT_OUT), (ended.clone(), 0.0, true, info.clone()));
+        }
+    }
+}
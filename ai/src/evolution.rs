@@ -0,0 +1,466 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use rust_driving_game_core::batch::{BatchRun, BatchRunner};
+use rust_driving_game_core::car::CarState;
+use rust_driving_game_core::input::{AnalogInput, InputProvider, Observation};
+use rust_driving_game_core::replay::TrackIdentity;
+use rust_driving_game_core::track::Track;
+
+use crate::env::EnvConfig;
+use crate::network::{Network, NetworkDriver, NETWORK_OUTPUTS};
+
+pub const CHECKPOINT_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrainerConfig {
+    pub env: EnvConfig,
+    pub population: usize,
+    pub hidden_layers: Vec<usize>,
+    // Episodes each genome is scored over, each reset with its own seed
+    pub episodes: u32,
+    // The best genomes, carried over unchanged to the next generation
+    pub elites: usize,
+    // Genomes drawn to pick each parent from, the fittest of them wins
+    pub tournament_size: usize,
+    // Chance of each weight being nudged, and the standard deviation of the nudge
+    pub mutation_rate: f32,
+    pub mutation_std: f32,
+    pub seed: u64,
+}
+
+impl Default for TrainerConfig {
+    fn default() -> Self {
+        let mut env = EnvConfig {
+            action_repeat: 4,
+            ..Default::default()
+        };
+        // Positions mean nothing on another track, the rays and speeds are what carry over
+        env.observation.pose = false;
+        TrainerConfig {
+            env,
+            population: 64,
+            hidden_layers: vec![8],
+            episodes: 1,
+            elites: 4,
+            tournament_size: 3,
+            mutation_rate: 0.1,
+            mutation_std: 0.3,
+            seed: 0,
+        }
+    }
+}
+
+impl TrainerConfig {
+    pub fn layer_sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![self.env.observation.len()];
+        sizes.extend(&self.hidden_layers);
+        sizes.push(NETWORK_OUTPUTS);
+        sizes
+    }
+
+    pub fn driver(&self, weights: Vec<f32>) -> NetworkDriver {
+        NetworkDriver {
+            network: Network {
+                layer_sizes: self.layer_sizes(),
+                weights,
+            },
+            observation: self.env.observation.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Genome {
+    pub weights: Vec<f32>,
+    // Total reward, averaged over the episodes
+    pub fitness: f32,
+    // Finished every episode
+    pub finished: bool,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct GenerationStats {
+    pub generation: u32,
+    pub best_fitness: f32,
+    pub mean_fitness: f32,
+    // Fraction of the population that finished every episode
+    pub finish_rate: f32,
+}
+
+impl fmt::Display for GenerationStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "generation {:>4}  best {:>9.2}  mean {:>9.2}  finished {:>5.1}%",
+            self.generation,
+            self.best_fitness,
+            self.mean_fitness,
+            self.finish_rate * 100.0
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum TrainError {
+    Io { path: PathBuf, source: std::io::Error },
+    Json(String),
+    UnsupportedVersion(u32),
+    TrackMismatch { expected: TrackIdentity, found: TrackIdentity },
+    // A setting asked for when resuming that isn't what the checkpoint was trained with
+    SettingMismatch { setting: &'static str, checkpoint: String, requested: String },
+}
+
+impl fmt::Display for TrainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrainError::Io { path, source } => write!(f, "could not access {}: {}", path.display(), source),
+            TrainError::Json(message) => write!(f, "malformed checkpoint: {}", message),
+            TrainError::UnsupportedVersion(version) => write!(
+                f,
+                "checkpoint version {} is newer than the supported version {}",
+                version, CHECKPOINT_VERSION
+            ),
+            TrainError::TrackMismatch { expected, found } => write!(
+                f,
+                "checkpoint was trained on {:?} ({:016x}) but the track is {:?} ({:016x})",
+                expected.name, expected.hash, found.name, found.hash
+            ),
+            TrainError::SettingMismatch {
+                setting,
+                checkpoint,
+                requested,
+            } => write!(
+                f,
+                "checkpoint was trained with {} {} but {} was asked for",
+                setting, checkpoint, requested
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TrainError {}
+
+// Everything needed to carry on training where it stopped. Each generation's random numbers
+// come from the seed and the generation number, so a resumed run matches an unbroken one
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub config: TrainerConfig,
+    // Missing from checkpoints saved before tracks were recorded, which are resumed on any track
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<TrackIdentity>,
+    // The generation `population` is waiting to be scored as
+    pub generation: u32,
+    pub population: Vec<Vec<f32>>,
+    pub best: Option<Genome>,
+    pub history: Vec<GenerationStats>,
+}
+
+impl Checkpoint {
+    pub fn load(path: impl AsRef<Path>) -> Result<Checkpoint, TrainError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|source| TrainError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let checkpoint: Checkpoint = serde_json::from_str(&contents).map_err(|e| TrainError::Json(e.to_string()))?;
+        if checkpoint.version > CHECKPOINT_VERSION {
+            return Err(TrainError::UnsupportedVersion(checkpoint.version));
+        }
+        Ok(checkpoint)
+    }
+
+    // Settings given again when resuming have to be what training started with, None is
+    // whatever the checkpoint has
+    pub fn check_settings(
+        &self,
+        population: Option<usize>,
+        hidden_layers: Option<&[usize]>,
+        seed: Option<u64>,
+    ) -> Result<(), TrainError> {
+        if let Some(population) = population.filter(|population| *population != self.config.population) {
+            return Err(TrainError::SettingMismatch {
+                setting: "population",
+                checkpoint: self.config.population.to_string(),
+                requested: population.to_string(),
+            });
+        }
+        if let Some(hidden_layers) = hidden_layers.filter(|layers| *layers != self.config.hidden_layers) {
+            return Err(TrainError::SettingMismatch {
+                setting: "hidden layers",
+                checkpoint: format!("{:?}", self.config.hidden_layers),
+                requested: format!("{:?}", hidden_layers),
+            });
+        }
+        if let Some(seed) = seed.filter(|seed| *seed != self.config.seed) {
+            return Err(TrainError::SettingMismatch {
+                setting: "seed",
+                checkpoint: self.config.seed.to_string(),
+                requested: seed.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // Written to a temporary file first so an interrupted save doesn't lose the last checkpoint
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TrainError> {
+        let path = path.as_ref();
+        let contents = serde_json::to_string(self).map_err(|e| TrainError::Json(e.to_string()))?;
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|source| TrainError::Io {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+}
+
+pub struct Trainer<'a> {
+    pub track: &'a Track,
+    pub checkpoint: Checkpoint,
+    pub threads: usize,
+}
+
+impl<'a> Trainer<'a> {
+    // A random first generation
+    pub fn new(track: &'a Track, config: TrainerConfig) -> Trainer<'a> {
+        let mut rng = generation_rng(config.seed, 0);
+        let weight_count = Network::weight_count(&config.layer_sizes());
+        let population = (0..config.population)
+            .map(|_| (0..weight_count).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        Trainer {
+            track,
+            checkpoint: Checkpoint {
+                version: CHECKPOINT_VERSION,
+                config,
+                track: Some(TrackIdentity::of(track)),
+                generation: 0,
+                population,
+                best: None,
+                history: Vec::new(),
+            },
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    // Fails if the checkpoint was trained on another track
+    pub fn resume(track: &'a Track, checkpoint: Checkpoint) -> Result<Trainer<'a>, TrainError> {
+        let found = TrackIdentity::of(track);
+        if let Some(expected) = checkpoint.track.as_ref().filter(|expected| **expected != found) {
+            return Err(TrainError::TrackMismatch {
+                expected: expected.clone(),
+                found,
+            });
+        }
+        Ok(Trainer {
+            track,
+            checkpoint,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        })
+    }
+
+    // Scores the current population, keeps hold of the best genome so far and breeds the next
+    // generation from it
+    pub fn step(&mut self) -> GenerationStats {
+        let genomes = self.evaluate(&self.checkpoint.population);
+        let count = genomes.len().max(1) as f32;
+        let stats = GenerationStats {
+            generation: self.checkpoint.generation,
+            best_fitness: genomes.iter().map(|g| g.fitness).fold(f32::NEG_INFINITY, f32::max),
+            mean_fitness: genomes.iter().map(|g| g.fitness).sum::<f32>() / count,
+            finish_rate: genomes.iter().filter(|g| g.finished).count() as f32 / count,
+        };
+        if let Some(champion) = genomes.iter().max_by(|a, b| a.fitness.total_cmp(&b.fitness)) {
+            if self.checkpoint.best.as_ref().is_none_or(|best| champion.fitness > best.fitness) {
+                self.checkpoint.best = Some(champion.clone());
+            }
+        }
+        self.checkpoint.generation += 1;
+        self.checkpoint.population = self.breed(genomes);
+        self.checkpoint.history.push(stats);
+        stats
+    }
+
+    // Runs every episode of every genome as its own run in a batch, each scored with the
+    // rewards an `Env` would have given it
+    pub fn evaluate(&self, population: &[Vec<f32>]) -> Vec<Genome> {
+        let config = &self.checkpoint.config;
+        let episodes = config.episodes.max(1) as usize;
+        let runs = population
+            .iter()
+            .enumerate()
+            .flat_map(|(index, weights)| {
+                (0..episodes).map(move |episode| {
+                    let driver = HeldDriver {
+                        driver: config.driver(weights.clone()),
+                        action_repeat: config.env.action_repeat.max(1) as u64,
+                        action: AnalogInput::default(),
+                    };
+                    BatchRun::new(&format!("genome {} episode {}", index, episode), Box::new(driver), config.env.physics)
+                        .with_start(config.env.start_slot(self.track, episode as u64))
+                })
+            })
+            .collect();
+        let report = BatchRunner::new(self.track, config.env.time_per_tick_s)
+            .with_threads(self.threads)
+            .run(runs);
+        population
+            .iter()
+            .zip(report.results.chunks(episodes))
+            .map(|(weights, results)| Genome {
+                weights: weights.clone(),
+                fitness: results
+                    .iter()
+                    .map(|result| config.env.reward.total(&result.progress, config.env.time_per_tick_s))
+                    .sum::<f32>()
+                    / episodes as f32,
+                finished: results.iter().all(|result| result.progress.state == CarState::Finished),
+            })
+            .collect()
+    }
+
+    fn breed(&self, mut genomes: Vec<Genome>) -> Vec<Vec<f32>> {
+        let config = &self.checkpoint.config;
+        let mut rng = generation_rng(config.seed, self.checkpoint.generation);
+        genomes.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+        let mut next = genomes
+            .iter()
+            .take(config.elites.min(config.population))
+            .map(|genome| genome.weights.clone())
+            .collect::<Vec<_>>();
+        while next.len() < config.population {
+            let mother = tournament(&genomes, config.tournament_size, &mut rng);
+            let father = tournament(&genomes, config.tournament_size, &mut rng);
+            let child = mother
+                .weights
+                .iter()
+                .zip(&father.weights)
+                .map(|(m, f)| {
+                    let weight = if rng.gen_bool(0.5) { *m } else { *f };
+                    if rng.gen::<f32>() < config.mutation_rate {
+                        weight + gaussian(&mut rng) * config.mutation_std
+                    } else {
+                        weight
+                    }
+                })
+                .collect();
+            next.push(child);
+        }
+        next
+    }
+}
+
+// Drives the way `Env::step` does, only asking the network for a new action every
+// `action_repeat` ticks
+struct HeldDriver {
+    driver: NetworkDriver,
+    action_repeat: u64,
+    action: AnalogInput,
+}
+
+impl InputProvider for HeldDriver {
+    fn get_input(&mut self, observation: &Observation) -> AnalogInput {
+        if observation.tick.is_multiple_of(self.action_repeat) {
+            self.action = self.driver.get_input(observation);
+        }
+        self.action
+    }
+}
+
+fn generation_rng(seed: u64, generation: u32) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(generation as u64);
+    rng
+}
+
+fn tournament<'g>(genomes: &'g [Genome], size: usize, rng: &mut ChaCha8Rng) -> &'g Genome {
+    genomes
+        .choose_multiple(rng, size.max(1))
+        .max_by(|a, b| a.fitness.total_cmp(&b.fitness))
+        .expect("the population is never empty")
+}
+
+// Box-Muller, which is all the normal distribution this needs
+fn gaussian(rng: &mut ChaCha8Rng) -> f32 {
+    let (u, v) = (rng.gen_range(f32::EPSILON..1.0), rng.gen::<f32>());
+    (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_driving_game_core::default_tracks::{make_track, PRESETS};
+
+    use super::*;
+    use crate::env::Env;
+
+    fn small_config() -> TrainerConfig {
+        let mut config = TrainerConfig {
+            population: 6,
+            episodes: 2,
+            ..Default::default()
+        };
+        config.env.start_jitter_m = 2.0;
+        config.env.start_jitter_radians = 0.1;
+        config
+    }
+
+    #[test]
+    fn genomes_score_what_the_env_rewards() {
+        let track = make_track();
+        let trainer = Trainer::new(&track, small_config());
+        let config = &trainer.checkpoint.config;
+        let genomes = trainer.evaluate(&trainer.checkpoint.population);
+        for genome in &genomes {
+            let driver = config.driver(genome.weights.clone());
+            let mut env = Env::new(&track, config.env.clone());
+            let mut total = 0.0;
+            for episode in 0..config.episodes {
+                let mut observation = env.reset(episode as u64);
+                loop {
+                    let (next, reward, done, _) = env.step(driver.act(&observation));
+                    total += reward;
+                    observation = next;
+                    if done {
+                        break;
+                    }
+                }
+            }
+            let fitness = total / config.episodes as f32;
+            assert!((genome.fitness - fitness).abs() < 1e-2, "{} against {}", genome.fitness, fitness);
+        }
+    }
+
+    #[test]
+    fn checkpoints_only_resume_as_they_were_started() {
+        let track = make_track();
+        let checkpoint = Trainer::new(&track, small_config()).checkpoint;
+        assert!(checkpoint.check_settings(Some(6), Some(&[8]), Some(0)).is_ok());
+        assert!(checkpoint.check_settings(None, None, None).is_ok());
+        assert!(matches!(
+            checkpoint.check_settings(Some(64), None, None),
+            Err(TrainError::SettingMismatch { setting: "population", .. })
+        ));
+        assert!(matches!(
+            checkpoint.check_settings(None, Some(&[8, 8]), None),
+            Err(TrainError::SettingMismatch { setting: "hidden layers", .. })
+        ));
+        assert!(matches!(
+            checkpoint.check_settings(None, None, Some(1)),
+            Err(TrainError::SettingMismatch { setting: "seed", .. })
+        ));
+
+        let other = PRESETS[1].build();
+        assert!(matches!(Trainer::resume(&other, checkpoint.clone()), Err(TrainError::TrackMismatch { .. })));
+        assert!(Trainer::resume(&track, checkpoint).is_ok());
+    }
+}
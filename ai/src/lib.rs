@@ -0,0 +1,3 @@
+pub mod env;
+pub mod evolution;
+pub mod network;
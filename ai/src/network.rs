@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use rust_driving_game_core::car::Car;
+use rust_driving_game_core::input::{AnalogInput, InputProvider, Observation as GameObservation};
+
+use crate::env::{Observation, ObservationConfig};
+
+// Observations are in metres and metres a second, which this brings to roughly -1..1 for a
+// network
+pub const OBSERVATION_SCALE: f32 = 1.0 / 50.0;
+// Throttle and brake, then steering
+pub const NETWORK_OUTPUTS: usize = 2;
+
+// A fully connected feed-forward network with tanh activations. Weights are stored layer by
+// layer, each neuron's bias followed by its input weights
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Network {
+    pub layer_sizes: Vec<usize>,
+    pub weights: Vec<f32>,
+}
+
+impl Network {
+    pub fn weight_count(layer_sizes: &[usize]) -> usize {
+        layer_sizes.windows(2).map(|pair| (pair[0] + 1) * pair[1]).sum()
+    }
+
+    pub fn new(layer_sizes: Vec<usize>, weights: Vec<f32>) -> Result<Network, String> {
+        let expected = Network::weight_count(&layer_sizes);
+        if layer_sizes.len() < 2 {
+            return Err("a network needs at least an input and an output layer".to_string());
+        }
+        if weights.len() != expected {
+            return Err(format!("layers {:?} need {} weights but there are {}", layer_sizes, expected, weights.len()));
+        }
+        Ok(Network { layer_sizes, weights })
+    }
+
+    pub fn forward(&self, inputs: &[f32]) -> Vec<f32> {
+        let mut values = inputs.to_vec();
+        let mut offset = 0;
+        for pair in self.layer_sizes.windows(2) {
+            let (inputs, outputs) = (pair[0], pair[1]);
+            values = (0..outputs)
+                .map(|neuron| {
+                    let start = offset + neuron * (inputs + 1);
+                    let weights = &self.weights[start + 1..start + 1 + inputs];
+                    let sum = values.iter().zip(weights).map(|(value, weight)| value * weight).sum::<f32>();
+                    (self.weights[start] + sum).tanh()
+                })
+                .collect();
+            offset += outputs * (inputs + 1);
+        }
+        values
+    }
+}
+
+// A network along with the observations it was trained on, so it can be put in any car
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NetworkDriver {
+    pub network: Network,
+    pub observation: ObservationConfig,
+}
+
+impl NetworkDriver {
+    // The first output is throttle when positive and brake when negative
+    pub fn act(&self, observation: &Observation) -> AnalogInput {
+        let inputs = observation.to_vec().iter().map(|value| value * OBSERVATION_SCALE).collect::<Vec<_>>();
+        let outputs = self.network.forward(&inputs);
+        let pedal = outputs.first().copied().unwrap_or_default();
+        let steering = outputs.get(1).copied().unwrap_or_default();
+        AnalogInput::new(pedal.max(0.0), (-pedal).max(0.0), steering)
+    }
+}
+
+impl InputProvider for NetworkDriver {
+    fn get_input(&mut self, observation: &GameObservation) -> AnalogInput {
+        let car = Car {
+            pos: observation.pos,
+            direction_radians: observation.direction_radians,
+            velocity: observation.velocity,
+            lateral_velocity: observation.lateral_velocity,
+            ..Default::default()
+        };
+        self.act(&self.observation.observe(observation.track, &car))
+    }
+}
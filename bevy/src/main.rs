@@ -1,27 +1,94 @@
 use bevy::prelude::*;
 // use bevy_ninepatch::*;
 use bevy_debug_grid::*;
-use rust_driving_game_core::car::{Car, CarState, PhysicsConstants};
+use rust_driving_game_core::car::{BicycleConstants, Car, CarState, PhysicsConstants, CAR_LENGTH_M, CAR_WIDTH_M};
 use rust_driving_game_core::car_progress::CarProgress;
 use rust_driving_game_core::coordinates::{LineType, Vec2d};
 use rust_driving_game_core::default_tracks;
+use rust_driving_game_core::gate::Gate;
 // use rust_driving_game_core::debug_grid::spawn_floor_grid;
-use rust_driving_game_core::input::{Accelerator, Direction, KeyInput};
+use rust_driving_game_core::input::{Accelerator, AnalogInput, Direction, KeyInput};
+use rust_driving_game_core::replay::Replay;
+use rust_driving_game_core::sensors::RaySensor;
 use rust_driving_game_core::track::Track;
 use rust_driving_game_core::gameloop::TIME_PER_TICK;
 
+// A track file or the name of a built-in track, see `default_tracks::PRESETS`
+#[derive(Resource, Default)]
+struct TrackPath(Option<String>);
+
+#[derive(Resource, Default)]
+struct Physics(PhysicsConstants);
+
+// Inputs for each tick the car has moved this run, written out as a replay to `path` when the
+// run ends
+#[derive(Resource, Default)]
+struct Recording {
+    path: Option<String>,
+    inputs: Vec<AnalogInput>,
+}
+
+// Game time since the car left the start line, kept a tick at a time as the headless loop does
+// so a replay of the run ends up exactly where the player did
+#[derive(Resource, Default)]
+struct RaceClock {
+    time_s: f32,
+}
+
+// Rays from the player's car to the walls, drawn while `visible`. V toggles them
+#[derive(Resource, Default)]
+struct RayDisplay {
+    sensor: RaySensor,
+    visible: bool,
+}
+
+fn has_flag(name: &str) -> bool {
+    std::env::args().skip(1).any(|arg| arg == name)
+}
+
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        }
+    }
+    None
+}
+
+fn physics_arg() -> PhysicsConstants {
+    match arg_value("--physics").as_deref() {
+        None | Some("arcade") => PhysicsConstants::default(),
+        Some("bicycle") => PhysicsConstants::Bicycle(BicycleConstants::default()),
+        Some(other) => panic!("Unknown physics model {}, expected arcade or bicycle", other),
+    }
+}
+
 fn main() {
     App::new()
         .add_plugins(DefaultPlugins)
+        .insert_resource(TrackPath(arg_value("--track")))
+        .insert_resource(Physics(physics_arg()))
+        .insert_resource(Recording {
+            path: arg_value("--record"),
+            ..default()
+        })
+        .init_resource::<RaceClock>()
+        .insert_resource(RayDisplay {
+            visible: has_flag("--rays"),
+            ..default()
+        })
         .insert_resource(ClearColor(Color::rgb(0.9, 0.9, 0.9)))
         // .insert_non_send_resource(track)
         .add_systems(Startup, (setup, spawn_floor_grid))
-        .add_systems(FixedUpdate, (move_car, check_state, reset_car))
+        // Gate crossings are worked out from the move made earlier in the same tick
+        .add_systems(FixedUpdate, (move_car, check_state, reset_car).chain())
+        .add_systems(Update, draw_rays)
         .insert_resource(Time::<Fixed>::from_seconds(TIME_PER_TICK.into()))
         .run();
 }
 
-const CAR_SIZE: Vec3 = Vec3::new(2.0, 5.0, 0.0);
+const CAR_SIZE: Vec3 = Vec3::new(CAR_WIDTH_M, CAR_LENGTH_M, 0.0);
 const CAR_COLOUR: Color = Color::rgb(0.3, 0.3, 0.7);
 const SCOREBOARD_FONT_SIZE: f32 = 40.0;
 const SCOREBOARD_TEXT_PADDING: Val = Val::Px(5.0);
@@ -30,6 +97,8 @@ const TEXT_COLOR: Color = Color::BLACK;
 const SCORE_COLOR: Color = Color::rgb(1.0, 0.5, 0.5);
 // const WALL_COLOR: Color = Color::rgb(0.8, 0.8, 0.8);
 const WALL_COLOR: Color = Color::BLACK;
+const RAY_HIT_COLOR: Color = Color::rgb(0.9, 0.2, 0.2);
+const RAY_CLEAR_COLOR: Color = Color::rgb(0.2, 0.7, 0.2);
 
 // This bundle is a collection of the components that define a "wall" in our game
 #[derive(Bundle)]
@@ -70,9 +139,10 @@ impl WallBundle {
             },
         };
         // transform.rotate_z((end_loc - start_loc).angle_between(Vec3::Y));
+        // Vec2::angle_between is signed, which we need for edges that aren't axis aligned
         bundle.sprite_bundle.transform.rotate_around(
             start_loc,
-            Quat::from_rotation_z((end_loc - start_loc).angle_between(Vec3::Y)),
+            Quat::from_rotation_z(Vec2::Y.angle_between((end_loc - start_loc).truncate())),
         );
         bundle
     }
@@ -81,14 +151,25 @@ impl WallBundle {
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    track_path: Res<TrackPath>,
     // mut nine_patches: ResMut<Assets<NinePatchBuilder<()>>>,
 ) {
     commands.spawn(Camera2dBundle::default());
 
+    let track = match &track_path.0 {
+        Some(path) => TrackComponent(
+            default_tracks::load_track_or_preset(std::path::Path::new(path))
+                .unwrap_or_else(|e| panic!("Failed to load track {}: {}", path, e)),
+        ),
+        None => TrackComponent(default_tracks::make_track()),
+    };
+
     commands.spawn((
         SpriteBundle {
             transform: Transform {
-                translation: Vec3::new(0.0, 0.0, 0.0),
+                translation: Vec3::new(track.0.start().x, track.0.start().y, 0.0),
+                // Sprite rotations are anticlockwise, car directions are clockwise
+                rotation: Quat::from_rotation_z(-track.0.start_direction_radians()),
                 scale: CAR_SIZE,
                 ..default()
             },
@@ -98,7 +179,7 @@ fn setup(
             },
             ..default()
         },
-        CarComponent(Car::default()),
+        CarComponent(Car::on_start_line(&track.0, "Player")),
         CarProgressComponent(CarProgress::default()),
     ));
 
@@ -146,8 +227,7 @@ fn setup(
         },
     ), StateBoard));
 
-    let track = TrackComponent(default_tracks::make_track());
-    track.0.sections.iter().for_each(|section| {
+    track.0.sections().iter().for_each(|section| {
         section.edges().iter().for_each(|edge| {
             commands.spawn(WallBundle::new(edge.0, edge.1));
         })
@@ -161,14 +241,31 @@ fn setup(
     //     asset_server.load("panel_atlas.png"),
     //     Rect::new(0., 0., 32., 32.),
     // );
-    let (finish_pos, finish_scale) = match &track.0.finish_line.line_type {
-        LineType::Horizontal(y) => (Vec3::new(0.0, *y, 0.0), Vec3::new(50.0, 4.0, 0.0) / 8.0),
-        LineType::Vertical(x) => (Vec3::new(*x, 0.0, 0.0), Vec3::new(4.0, 50.0, 0.0) / 8.0),
-        LineType::Diagonal(_, _) => unimplemented!(),
+    let (finish_pos, finish_scale, finish_rotation) = match track.0.finish_line() {
+        Gate::Segment(segment) => {
+            let start = Vec2::new(segment.start.x, segment.start.y);
+            let along = Vec2::new(segment.end.x, segment.end.y) - start;
+            // The texture is 64 pixels square
+            let scale = Vec3::new(along.length() / 64.0, 0.5, 0.0);
+            (start.extend(0.0) + along.extend(0.0) / 2.0, scale, Quat::from_rotation_z(along.y.atan2(along.x)))
+        }
+        Gate::Line(boundary) => match boundary.line_type {
+            LineType::Horizontal(y) => (Vec3::new(0.0, y, 0.0), Vec3::new(50.0, 4.0, 0.0) / 8.0, Quat::IDENTITY),
+            LineType::Vertical(x) => (Vec3::new(x, 0.0, 0.0), Vec3::new(4.0, 50.0, 0.0) / 8.0, Quat::IDENTITY),
+            LineType::Diagonal(m, c) => {
+                // Put the sprite where the line passes closest to the start
+                let start = Vec2::new(track.0.start().x, track.0.start().y);
+                let along = Vec2::new(1.0, m).normalize();
+                let on_line = Vec2::new(0.0, c);
+                let pos = on_line + along * (start - on_line).dot(along);
+                (pos.extend(0.0), Vec3::new(50.0, 4.0, 0.0) / 8.0, Quat::from_rotation_z(m.atan()))
+            }
+        },
     };
     let finish_line_bundle = SpriteBundle {
         transform: Transform {
             translation: finish_pos,
+            rotation: finish_rotation,
             scale: finish_scale,
             ..default()
         },
@@ -203,10 +300,39 @@ struct StateBoard;
 #[derive(Component)]
 struct ScoreBoard;
 
+// Inputs smaller than this are treated as the stick or trigger being at rest
+const GAMEPAD_DEADZONE: f32 = 0.05;
+
+fn gamepad_input(
+    gamepads: &Gamepads,
+    axes: &Axis<GamepadAxis>,
+    buttons: &Axis<GamepadButton>,
+) -> Option<AnalogInput> {
+    let gamepad = gamepads.iter().next()?;
+    let steering = axes
+        .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+        .unwrap_or(0.0);
+    let throttle = buttons
+        .get(GamepadButton::new(gamepad, GamepadButtonType::RightTrigger2))
+        .unwrap_or(0.0);
+    let brake = buttons
+        .get(GamepadButton::new(gamepad, GamepadButtonType::LeftTrigger2))
+        .unwrap_or(0.0);
+    if [steering, throttle, brake].iter().all(|v| v.abs() < GAMEPAD_DEADZONE) {
+        None
+    } else {
+        Some(AnalogInput::new(throttle, brake, steering))
+    }
+}
+
 fn move_car(
     keyboard_input: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    gamepad_buttons: Res<Axis<GamepadButton>>,
     mut query: Query<(&mut Transform, &mut CarComponent, &mut CarProgressComponent)>,
-    time: Res<Time>,
+    physics: Res<Physics>,
+    mut recording: ResMut<Recording>,
 ) {
     let mut car = query.single_mut();
     let key_input = {
@@ -222,22 +348,20 @@ fn move_car(
             None
         }
     };
-    match car.1 .0.state {
-        CarState::StartLine if key_input.is_some() => {
-            car.1 .0.state = CarState::Racing;
-            car.2 .0.start_time = time.elapsed_seconds();
-        }
-        CarState::Racing => {
-            let consts = PhysicsConstants::default();
-            if let Some((x_d, y_d, theta_d)) =
-                car.1
-                     .0
-                    .update_position(&consts, time.delta_seconds(), key_input) {
-                car.0.rotate_local_z(theta_d);
-                car.0.translation += Vec3::new(x_d, y_d, 0.0);
-            }
+    // The keyboard wins if both are in use
+    let input = key_input
+        .map(AnalogInput::from)
+        .or_else(|| gamepad_input(&gamepads, &gamepad_axes, &gamepad_buttons));
+    // The first input moves the car off the start line in the same tick, as in the headless loop
+    if car.1 .0.state == CarState::Racing || (car.1 .0.state == CarState::StartLine && input.is_some()) {
+        recording.inputs.push(input.unwrap_or_default());
+        if let Some((x_d, y_d, theta_d)) =
+            car.1
+                 .0
+                .update_position(&physics.0, TIME_PER_TICK, Some(input.unwrap_or_default())) {
+            car.0.rotate_local_z(theta_d);
+            car.0.translation += Vec3::new(x_d, y_d, 0.0);
         }
-        _ => {}
     }
 }
 
@@ -245,35 +369,104 @@ fn reset_car(
     keyboard_input: Res<Input<KeyCode>>,
     mut car_query: Query<(&mut Transform, &mut CarComponent, &mut CarProgressComponent)>,
     track_query: Query<&TrackComponent>,
+    mut recording: ResMut<Recording>,
+    mut clock: ResMut<RaceClock>,
 ) {
     let track = track_query.single();
     let mut car_result = car_query.single_mut();
-    let start_pos = track.0.start;
+    let start_pos = track.0.start();
+    let start_direction = track.0.start_direction_radians();
     if keyboard_input.pressed(KeyCode::R) {
-        car_result.1 .0.reset(start_pos);
+        car_result.1 .0.reset(start_pos, start_direction);
         car_result.2 .0 = CarProgress::default();
         car_result.0.translation = Vec3::new(start_pos.x, start_pos.y, 0.0);
-        car_result.0.rotation = Quat::from_rotation_z(0.0);
+        car_result.0.rotation = Quat::from_rotation_z(-start_direction);
+        recording.inputs.clear();
+        clock.time_s = 0.0;
     }
 }
 
 fn check_state(
-    mut car_query: Query<(&mut CarComponent, &mut CarProgressComponent)>,
+    mut car_query: Query<(&mut CarComponent, &mut CarProgressComponent, &mut Transform)>,
     track_res: Query<&TrackComponent>,
     mut score_query: Query<(&mut Text), (With<ScoreBoard>, Without<StateBoard>)>,
     mut state_query: Query<(&mut Text), With<StateBoard>>,
-    time: Res<Time>,
+    physics: Res<Physics>,
+    mut recording: ResMut<Recording>,
+    mut clock: ResMut<RaceClock>,
 ) {
     let mut car = car_query.single_mut();
     let track = track_res.single();
+    // Only a car that moved this tick is racing here
+    let moved = car.0 .0.state == CarState::Racing;
     car.0
          .0
-        .update_state(&track.0, &mut car.1 .0, time.elapsed_seconds());
+        .update_state(&track.0, &mut car.1 .0, clock.time_s, TIME_PER_TICK);
+    if moved {
+        clock.time_s += TIME_PER_TICK;
+    }
+    // Bouncing or scraping off a wall moves the car outside of update_position
+    let pos = car.0 .0.pos;
+    car.2.translation = Vec3::new(pos.x, pos.y, 0.0);
+    car.2.rotation = Quat::from_rotation_z(-car.0 .0.direction_radians);
     let state = car.0 .0.state;
+    if state != CarState::Racing && !recording.inputs.is_empty() {
+        save_recording(&mut recording, &track.0, &physics.0, &car.0 .0.label, &car.1 .0);
+    }
     let mut timer = score_query.single_mut();
     let mut state_board = state_query.single_mut();
     if state == CarState::Racing {
-        timer.sections[1].value = format!("{:.4}", time.elapsed_seconds() - car.1 .0.start_time);
+        timer.sections[1].value = format!("{:.4}", clock.time_s - car.1 .0.start_time);
+    } else if let Some(end_time) = car.1 .0.end_time {
+        // The official time, from the moment the car crossed the line rather than the tick after
+        timer.sections[1].value = format!("{:.4}", end_time - car.1 .0.start_time);
+    }
+    let completion = car.1 .0.completion * 100.0;
+    state_board.sections[0].value = if track.0.laps() > 1 {
+        let lap = (car.1 .0.laps_completed + 1).min(track.0.laps());
+        format!("{} - lap {}/{} - {:.0}%", state, lap, track.0.laps(), completion)
+    } else {
+        format!("{} - {:.0}%", state, completion)
+    };
+}
+
+fn draw_rays(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut ray_display: ResMut<RayDisplay>,
+    mut gizmos: Gizmos,
+    car_query: Query<&CarComponent>,
+    track_query: Query<&TrackComponent>,
+) {
+    if keyboard_input.just_pressed(KeyCode::V) {
+        ray_display.visible = !ray_display.visible;
+    }
+    if !ray_display.visible {
+        return;
+    }
+    let car = &car_query.single().0;
+    let origin = Vec2::new(car.pos.x, car.pos.y);
+    for hit in ray_display.sensor.cast(&track_query.single().0, car.pos, car.direction_radians) {
+        let colour = if hit.hit_wall { RAY_HIT_COLOR } else { RAY_CLEAR_COLOR };
+        gizmos.line_2d(origin, Vec2::new(hit.end.x, hit.end.y), colour);
+    }
+}
+
+fn save_recording(
+    recording: &mut Recording,
+    track: &Track,
+    physics: &PhysicsConstants,
+    label: &str,
+    progress: &CarProgress,
+) {
+    let inputs = std::mem::take(&mut recording.inputs);
+    if let Some(path) = &recording.path {
+        let replay = Replay::from_run(track, physics, TIME_PER_TICK, label, inputs, progress.clone());
+        if let Err(e) = replay.verify(track) {
+            warn!("The replay doesn't reproduce the run: {}", e);
+        }
+        match replay.save(path) {
+            Ok(()) => info!("Saved replay to {}", path),
+            Err(e) => error!("Failed to save replay {}: {}", path, e),
+        }
     }
-    state_board.sections[0].value = state.to_string().into();
 }
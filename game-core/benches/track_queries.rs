@@ -0,0 +1,138 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use rust_driving_game_core::batch::{BatchRun, BatchRunner};
+use rust_driving_game_core::car::{PhysicsConstants, TerminationCondition};
+use rust_driving_game_core::coordinates::{segment_intersection, Vec2d};
+use rust_driving_game_core::gameloop::TIME_PER_TICK;
+use rust_driving_game_core::gate::SegmentGate;
+use rust_driving_game_core::input::{Accelerator, Direction, KeyInput, SingleInput};
+use rust_driving_game_core::track::{ParallelRectSection, Track, TrackMetadata};
+
+// Compares the track queries against the way they were answered before there was a spatial index,
+// going through every section and asking each for its edges again. Run with `cargo bench`
+
+const SECTIONS: usize = 500;
+const SECTION_LENGTH_M: f32 = 20.0;
+const QUERIES: usize = 200_000;
+const RAYS: usize = 20_000;
+const CARS: usize = 16;
+// As in `collision.rs`
+const WALL_PROBE_M: f32 = 1e-3;
+
+// A straight road made of many short overlapping pieces, each a little offset from the last
+fn long_track() -> Track {
+    let sections = (0..SECTIONS)
+        .map(|i| {
+            let drift = (i as f32 * 0.3).sin() * 5.0;
+            Box::new(ParallelRectSection {
+                left_x: -50.0 + drift,
+                right_x: 50.0 + drift,
+                bottom_y: i as f32 * SECTION_LENGTH_M - 10.0,
+                top_y: (i + 1) as f32 * SECTION_LENGTH_M + 1.0,
+            }) as _
+        })
+        .collect::<Vec<_>>();
+    Track::new(
+        TrackMetadata {
+            name: "Long straight".to_string(),
+            author: None,
+        },
+        Default::default(),
+        0.0,
+        SegmentGate::across(Vec2d::new(0.0, (SECTIONS - 5) as f32 * SECTION_LENGTH_M), 90.0, 0.0).into(),
+        sections,
+        TerminationCondition::Seconds(120.0),
+    )
+}
+
+fn linear_is_within_track(track: &Track, point: &Vec2d) -> bool {
+    track.sections().iter().any(|section| section.is_within(point))
+}
+
+fn linear_cast_ray(track: &Track, origin: Vec2d, heading_radians: f32, max_length_m: f32) -> f32 {
+    let end = origin + Vec2d::from_heading(heading_radians) * max_length_m;
+    let mut nearest = 1.0;
+    for section in track.sections() {
+        for edge in section.edges() {
+            if let Some((t, _)) = segment_intersection(origin, end, edge.0, edge.1) {
+                let point = origin.lerp(end, t);
+                let normal = (edge.1 - edge.0).normalize().perp_right() * WALL_PROBE_M;
+                let is_wall = !(linear_is_within_track(track, &(point + normal))
+                    && linear_is_within_track(track, &(point - normal)));
+                if t < nearest && is_wall {
+                    nearest = t;
+                }
+            }
+        }
+    }
+    nearest * max_length_m
+}
+
+// Spread over the road and a bit beyond it, the same every run
+fn points(count: usize) -> Vec<Vec2d> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 40) as f32 / (1u64 << 24) as f32
+    };
+    let length = SECTIONS as f32 * SECTION_LENGTH_M;
+    (0..count).map(|_| Vec2d::new(next() * 140.0 - 70.0, next() * length)).collect()
+}
+
+fn time<T>(name: &str, repeat: impl Fn() -> T) -> f64 {
+    let start = Instant::now();
+    black_box(repeat());
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("  {:<12} {:>9.3}s", name, elapsed);
+    elapsed
+}
+
+fn compare(name: &str, track: &Track, linear: impl Fn(&Track) -> usize, indexed: impl Fn(&Track) -> usize) {
+    println!("{}", name);
+    assert_eq!(linear(track), indexed(track), "the index changed the answers");
+    let before = time("linear", || linear(track));
+    let after = time("indexed", || indexed(track));
+    println!("  speedup      {:>9.1}x", before / after.max(f64::EPSILON));
+}
+
+fn main() {
+    println!("{} sections", SECTIONS);
+    let track = long_track();
+    let queries = points(QUERIES);
+    compare(
+        &format!("is_within_track x {}", QUERIES),
+        &track,
+        |track| queries.iter().filter(|point| linear_is_within_track(track, point)).count(),
+        |track| queries.iter().filter(|point| track.is_within_track(point)).count(),
+    );
+
+    let origins = points(RAYS);
+    let rays = |cast: &dyn Fn(Vec2d, f32) -> f32| {
+        origins
+            .iter()
+            .enumerate()
+            .map(|(i, origin)| cast(*origin, i as f32) as usize)
+            .sum()
+    };
+    compare(
+        &format!("cast_ray x {}", RAYS),
+        &track,
+        |track| rays(&|origin, heading| linear_cast_ray(track, origin, heading, 100.0)),
+        |track| rays(&|origin, heading| track.cast_ray(origin, heading, 100.0)),
+    );
+
+    // Whole races only run indexed, so these are just timed
+    println!("batch of {} cars", CARS);
+    let runs = (0..CARS)
+        .map(|i| {
+            let steer = [None, Some(Direction::Left)][i % 2];
+            let key = KeyInput::new(Some(Accelerator::Accelerate), steer);
+            BatchRun::new("car", Box::new(SingleInput::from(key)), PhysicsConstants::default())
+        })
+        .collect();
+    let report = BatchRunner::new(&track, TIME_PER_TICK).with_threads(1).run(runs);
+    println!("  indexed      {:>9.3}s  {:.0} car ticks/s", report.elapsed_s, report.car_ticks_per_second());
+}
@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::car::{Car, PhysicsConstants};
+use crate::car_progress::CarProgress;
+use crate::gameloop::run_until;
+use crate::input::InputProvider;
+use crate::track::{GridSlot, Track};
+
+// One car driven by one controller from the start line, on its own
+pub struct BatchRun {
+    pub label: String,
+    pub input: Box<dyn InputProvider + Send>,
+    pub physics: PhysicsConstants,
+    // Where the car starts instead of the start line
+    pub start: Option<GridSlot>,
+}
+
+impl BatchRun {
+    pub fn new(label: &str, input: Box<dyn InputProvider + Send>, physics: PhysicsConstants) -> BatchRun {
+        BatchRun {
+            label: label.to_string(),
+            input,
+            physics,
+            start: None,
+        }
+    }
+
+    pub fn with_start(mut self, start: GridSlot) -> BatchRun {
+        self.start = Some(start);
+        self
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BatchResult {
+    pub label: String,
+    pub progress: CarProgress,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BatchReport {
+    // In the same order as the runs were given
+    pub results: Vec<BatchResult>,
+    pub threads: usize,
+    pub car_ticks: u64,
+    pub elapsed_s: f64,
+}
+
+impl BatchReport {
+    pub fn car_ticks_per_second(&self) -> f64 {
+        self.car_ticks as f64 / self.elapsed_s.max(f64::EPSILON)
+    }
+}
+
+// Runs many independent cars across threads, all sharing the one track. Every run is simulated
+// start to finish on a single thread exactly as `run_until` would, so the results don't depend on
+// how many threads there are or which one picked up which run
+pub struct BatchRunner<'a> {
+    pub track: &'a Track,
+    pub time_per_tick_s: f32,
+    pub threads: usize,
+}
+
+impl<'a> BatchRunner<'a> {
+    // Uses every core
+    pub fn new(track: &'a Track, time_per_tick_s: f32) -> BatchRunner<'a> {
+        BatchRunner {
+            track,
+            time_per_tick_s,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> BatchRunner<'a> {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn run(&self, runs: Vec<BatchRun>) -> BatchReport {
+        let start = Instant::now();
+        let threads = self.threads.clamp(1, runs.len().max(1));
+        // Runs can take very different numbers of ticks, so threads take the next one waiting
+        // rather than a fixed share
+        let next = AtomicUsize::new(0);
+        let runs = runs.into_iter().map(|run| Mutex::new(Some(run))).collect::<Vec<_>>();
+        let mut finished = thread::scope(|scope| {
+            let handles = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut finished = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(slot) = runs.get(index) else {
+                                break;
+                            };
+                            let run = slot.lock().expect("a batch run was poisoned").take();
+                            if let Some(run) = run {
+                                finished.push((index, self.run_one(run)));
+                            }
+                        }
+                        finished
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("a batch thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        finished.sort_by_key(|(index, _)| *index);
+        let results = finished.into_iter().map(|(_, result)| result).collect::<Vec<_>>();
+        BatchReport {
+            car_ticks: results.iter().map(|result| result.progress.ticks).sum(),
+            results,
+            threads,
+            elapsed_s: start.elapsed().as_secs_f64(),
+        }
+    }
+
+    fn run_one(&self, run: BatchRun) -> BatchResult {
+        let mut car = match &run.start {
+            Some(slot) => Car::on_grid(slot, &run.label),
+            None => Car::on_start_line(self.track, &run.label),
+        };
+        let mut input: Box<dyn InputProvider> = run.input;
+        let mut progress = run_until(vec![(&mut car, &mut input)], self.track, &run.physics, self.time_per_tick_s);
+        BatchResult {
+            label: run.label,
+            progress: progress.pop().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::{ArcadeConstants, BicycleConstants};
+    use crate::coordinates::Vec2d;
+    use crate::default_tracks::make_track;
+    use crate::gameloop::TIME_PER_TICK;
+    use crate::line_follower::LineFollower;
+
+    fn runs() -> Vec<BatchRun> {
+        let physics = [
+            PhysicsConstants::Arcade(ArcadeConstants::default()),
+            PhysicsConstants::Bicycle(BicycleConstants::default()),
+        ];
+        (0..6)
+            .map(|i| {
+                let physics = physics[i % 2];
+                let run = BatchRun::new(&format!("car {}", i), Box::new(LineFollower::new(physics)), physics);
+                // Some from off the start line, so the runs don't all take as long
+                if i < 2 {
+                    run
+                } else {
+                    run.with_start(GridSlot {
+                        pos: Vec2d::new(i as f32, -(i as f32)),
+                        direction_radians: 0.1 * i as f32,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn results_do_not_depend_on_the_number_of_threads() {
+        let track = make_track();
+        let alone = BatchRunner::new(&track, TIME_PER_TICK).with_threads(1).run(runs());
+        let together = BatchRunner::new(&track, TIME_PER_TICK).with_threads(4).run(runs());
+        assert_eq!((alone.threads, together.threads), (1, 4));
+        let json = |report: &BatchReport| serde_json::to_string(&report.results).unwrap();
+        assert_eq!(json(&alone), json(&together));
+        assert_eq!(alone.car_ticks, together.car_ticks);
+    }
+}
@@ -1,9 +1,22 @@
-use crate::car_progress::CarProgress;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::car_progress::{CarProgress, GATE_REARM_DISTANCE_M};
+use crate::collision::{CollisionResponse, OrientedRect, WallContact};
 use crate::coordinates::Vec2d;
-use crate::input::{Accelerator, Direction, KeyInput};
-use crate::track::Track;
+use crate::input::AnalogInput;
+use crate::track::{GridSlot, Track};
+
+pub const CAR_WIDTH_M: f32 = 2.0;
+pub const CAR_LENGTH_M: f32 = 5.0;
+// Gap left between a car and the wall it has bounced off
+const REBOUND_SEPARATION_M: f32 = 0.01;
+// Below this a car knocked to a stop keeps its heading rather than turning to follow whatever is
+// left of its velocity
+const MIN_HEADING_SPEED_MS: f32 = 0.01;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Default)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Default, Serialize, Deserialize)]
 pub enum CarState {
     #[default]
     StartLine,
@@ -13,6 +26,7 @@ pub enum CarState {
     TimedOut,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TerminationCondition {
     Ticks(u64),
     Seconds(f32),
@@ -27,19 +41,35 @@ impl TerminationCondition {
     }
 }
 
-impl CarState {
-    pub fn to_string(&self) -> String {
-        match self {
-            CarState::StartLine => "StartLine".to_string(),
-            CarState::Finished => "Finished".to_string(),
-            CarState::Racing => "Racing".to_string(),
-            CarState::Crashed => "Crashed".to_string(),
-            CarState::TimedOut => "TimedOut".to_string(),
-        }
+impl fmt::Display for CarState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CarState::StartLine => "StartLine",
+            CarState::Finished => "Finished",
+            CarState::Racing => "Racing",
+            CarState::Crashed => "Crashed",
+            CarState::TimedOut => "TimedOut",
+        };
+        f.write_str(name)
     }
 }
 
-pub struct PhysicsConstants {
+// Which model moves the car, along with that model's parameters
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PhysicsConstants {
+    Arcade(ArcadeConstants),
+    Bicycle(BicycleConstants),
+}
+
+impl Default for PhysicsConstants {
+    fn default() -> Self {
+        PhysicsConstants::Arcade(ArcadeConstants::default())
+    }
+}
+
+// The car moves wherever it points and turns at a fixed rate, even when stationary
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ArcadeConstants {
     pub forward_acceleration_mss: f32,
     pub braking_acceleration_mss: f32,
     pub reverse_acceleration_mss: f32,
@@ -48,9 +78,9 @@ pub struct PhysicsConstants {
     pub turn_rate_rs: f32,
 }
 
-impl Default for PhysicsConstants {
+impl Default for ArcadeConstants {
     fn default() -> Self {
-        PhysicsConstants {
+        ArcadeConstants {
             // mph * 4/9 gives ms-1
             forward_acceleration_mss: 20.0,
             braking_acceleration_mss: 4.0,
@@ -63,6 +93,43 @@ impl Default for PhysicsConstants {
     }
 }
 
+// Kinematic bicycle model. Steering sets a turn radius from the wheelbase, so the car can't turn
+// without moving. The front tyres cap how fast it can yaw (understeer) and the rear tyres cap how
+// quickly sideways velocity is scrubbed off (drift)
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BicycleConstants {
+    pub forward_acceleration_mss: f32,
+    pub braking_acceleration_mss: f32,
+    pub reverse_acceleration_mss: f32,
+    pub max_forward_speed_ms: f32,
+    pub max_reverse_speed_ms: f32,
+    pub wheelbase_m: f32,
+    pub max_steering_radians: f32,
+    // Steering lock is scaled down linearly to this fraction of itself at max_forward_speed_ms
+    pub high_speed_steering_fraction: f32,
+    pub front_grip_mss: f32,
+    pub rear_grip_mss: f32,
+}
+
+impl Default for BicycleConstants {
+    fn default() -> Self {
+        BicycleConstants {
+            forward_acceleration_mss: 20.0,
+            braking_acceleration_mss: 8.0,
+            reverse_acceleration_mss: 2.0,
+            max_forward_speed_ms: 50.0,
+            max_reverse_speed_ms: 10.0,
+            wheelbase_m: 3.0,
+            // Roughly 35 degrees
+            max_steering_radians: 0.6,
+            high_speed_steering_fraction: 0.3,
+            // A touch more grip at the front than the rear, so the car will slide out at the limit
+            front_grip_mss: 12.0,
+            rear_grip_mss: 10.0,
+        }
+    }
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct Car {
     // x goes from left to right: +x points right
@@ -71,7 +138,11 @@ pub struct Car {
     pub previous_pos: Vec2d,
     // This is relative to the y axis. 0 points up, 90 points right
     pub direction_radians: f32,
+    pub previous_direction_radians: f32,
+    // Speed along direction_radians
     pub velocity: f32,
+    // Sideways speed, positive to the right of the car. Only the bicycle model lets the car slide
+    pub lateral_velocity: f32,
     pub state: CarState,
     pub label: String,
 }
@@ -82,98 +153,587 @@ impl Car {
             pos,
             previous_pos: pos,
             direction_radians: 0.0,
+            previous_direction_radians: 0.0,
             velocity: 0.0,
+            lateral_velocity: 0.0,
             state: CarState::StartLine,
             label: label.to_string(),
         }
     }
 
-    pub fn reset(&mut self, start_line: Vec2d) {
+    pub fn on_start_line(track: &Track, label: &str) -> Car {
+        Car {
+            direction_radians: track.start_direction_radians(),
+            previous_direction_radians: track.start_direction_radians(),
+            ..Car::new(track.start(), label)
+        }
+    }
+
+    pub fn on_grid(slot: &GridSlot, label: &str) -> Car {
+        Car {
+            direction_radians: slot.direction_radians,
+            previous_direction_radians: slot.direction_radians,
+            ..Car::new(slot.pos, label)
+        }
+    }
+
+    pub fn reset(&mut self, start_line: Vec2d, direction_radians: f32) {
         self.pos = start_line;
         self.previous_pos = start_line;
         self.velocity = 0.0;
-        self.direction_radians = 0.0;
+        self.lateral_velocity = 0.0;
+        self.direction_radians = direction_radians;
+        self.previous_direction_radians = direction_radians;
         self.state = CarState::StartLine;
     }
+    pub fn body(&self) -> OrientedRect {
+        OrientedRect {
+            centre: self.pos,
+            width: CAR_WIDTH_M,
+            length: CAR_LENGTH_M,
+            direction_radians: self.direction_radians,
+        }
+    }
+
+    pub fn previous_body(&self) -> OrientedRect {
+        OrientedRect {
+            centre: self.previous_pos,
+            direction_radians: self.previous_direction_radians,
+            ..self.body()
+        }
+    }
+
+    pub fn velocity_vector(&self) -> Vec2d {
+        let forward = Vec2d::from_heading(self.direction_radians);
+        forward * self.velocity + forward.perp_right() * self.lateral_velocity
+    }
+
     pub fn x_velocity(&self) -> f32 {
-        self.velocity * self.direction_radians.sin()
+        self.velocity_vector().x
     }
 
     pub fn y_velocity(&self) -> f32 {
-        self.velocity * self.direction_radians.cos()
+        self.velocity_vector().y
     }
 
-    pub fn update_position(
+    // Anything that converts into an `AnalogInput` can drive the car, including `KeyInput`
+    pub fn update_position<I: Into<AnalogInput>>(
         &mut self,
         consts: &PhysicsConstants,
         delta_time_s: f32,
-        key_input: Option<KeyInput>,
+        input: Option<I>,
     ) -> Option<(f32, f32, f32)> {
-        // position += velocity * delta + acceleration * delta * delta * 0.5
-        if key_input.is_some() && self.state == CarState::StartLine {
+        let input = input.map(Into::into);
+        if input.is_some() && self.state == CarState::StartLine {
             self.state = CarState::Racing;
         }
 
         if self.state == CarState::Racing {
-            let accel = key_input.map_or(0.0, |k| match k.acceleration {
-                None => 0.0,
-                Some(Accelerator::Accelerate) => consts.forward_acceleration_mss,
-                Some(Accelerator::Brake) => {
-                    if self.velocity > 0.0 {
-                        -consts.braking_acceleration_mss
-                    } else {
-                        -consts.reverse_acceleration_mss
-                    }
-                }
-            });
-            self.velocity += accel * delta_time_s;
-            // Must be a neater way to get the sign right
-            let sign = if accel > 0.0 { 1.0 } else { -1.0 };
-            let pos_change = self.velocity * delta_time_s + 0.5 * delta_time_s * accel * accel * (sign);
-            let capped_pos_change = if (pos_change > consts.max_forward_speed_ms * delta_time_s) {
-                self.velocity * delta_time_s
-            } else {
-                pos_change
-            };
-            let direction_change = key_input.map_or(0.0, |k| match k.direction {
-                None => 0.0,
-                Some(Direction::Left) => -consts.turn_rate_rs,
-                Some(Direction::Right) => consts.turn_rate_rs,
-            });
-            let theta_change = delta_time_s * direction_change;
-            self.direction_radians += theta_change;
-            let x_change = capped_pos_change * self.direction_radians.sin();
-            let y_change = capped_pos_change * self.direction_radians.cos();
-            self.previous_pos = self.pos;
-            self.pos.x += x_change;
-            self.pos.y += y_change;
-            Some((x_change, y_change, -theta_change))
+            let previous_pos = self.pos;
+            let previous_direction = self.direction_radians;
+            match consts {
+                PhysicsConstants::Arcade(arcade) => self.update_arcade(arcade, delta_time_s, input.unwrap_or_default()),
+                PhysicsConstants::Bicycle(bicycle) => self.update_bicycle(bicycle, delta_time_s, input.unwrap_or_default()),
+            }
+            self.previous_pos = previous_pos;
+            self.previous_direction_radians = previous_direction;
+            let change = self.pos - previous_pos;
+            Some((change.x, change.y, previous_direction - self.direction_radians))
         } else {
             None
         }
     }
 
-    pub fn update_state(&mut self, track: &Track, game_state: &mut CarProgress, game_time_s: f32) {
-        match self.state {
-            CarState::Racing => {
-                if track.is_within_track(&self.pos) {
-                    if track.is_finished(&self.pos) {
-                        game_state.end_time = Some(game_time_s);
-                        self.state = CarState::Finished;
-                    } else if track.termination_condition.is_timed_out(game_state.ticks, game_time_s - game_state.start_time) {
-                        self.state = CarState::TimedOut;
-                        game_state.end_time = Some(game_time_s);
-                    } else {
-                        game_state.ticks += 1;
-                        self.state = CarState::Racing;
+    fn braking_mss(&self, braking_acceleration_mss: f32, reverse_acceleration_mss: f32) -> f32 {
+        if self.velocity > 0.0 {
+            braking_acceleration_mss
+        } else {
+            reverse_acceleration_mss
+        }
+    }
+
+    fn update_arcade(&mut self, consts: &ArcadeConstants, delta_time_s: f32, input: AnalogInput) {
+        // position += velocity * delta + acceleration * delta * delta * 0.5
+        let accel = input.throttle * consts.forward_acceleration_mss
+            - input.brake * self.braking_mss(consts.braking_acceleration_mss, consts.reverse_acceleration_mss);
+        self.velocity += accel * delta_time_s;
+        // Must be a neater way to get the sign right
+        let sign = if accel > 0.0 { 1.0 } else { -1.0 };
+        let pos_change = self.velocity * delta_time_s + 0.5 * delta_time_s * accel * accel * (sign);
+        let capped_pos_change = if pos_change > consts.max_forward_speed_ms * delta_time_s {
+            self.velocity * delta_time_s
+        } else {
+            pos_change
+        };
+        let direction_change = input.steering * consts.turn_rate_rs;
+        let theta_change = delta_time_s * direction_change;
+        self.direction_radians += theta_change;
+        let x_change = capped_pos_change * self.direction_radians.sin();
+        let y_change = capped_pos_change * self.direction_radians.cos();
+        self.pos.x += x_change;
+        self.pos.y += y_change;
+    }
+
+    fn update_bicycle(&mut self, consts: &BicycleConstants, delta_time_s: f32, input: AnalogInput) {
+        let throttle = input.throttle * consts.forward_acceleration_mss
+            - input.brake * self.braking_mss(consts.braking_acceleration_mss, consts.reverse_acceleration_mss);
+        let steering = input.steering;
+
+        // Work in world space so that turning the car leaves its momentum where it was
+        let mut velocity = self.velocity_vector() + Vec2d::from_heading(self.direction_radians) * (throttle * delta_time_s);
+
+        let speed = self.velocity;
+        let speed_fraction = (speed.abs() / consts.max_forward_speed_ms).min(1.0);
+        let lock = consts.max_steering_radians * (1.0 + (consts.high_speed_steering_fraction - 1.0) * speed_fraction);
+        let mut yaw_rate = speed * (steering * lock).tan() / consts.wheelbase_m;
+        if speed.abs() > f32::EPSILON {
+            let max_yaw_rate = consts.front_grip_mss / speed.abs();
+            yaw_rate = yaw_rate.clamp(-max_yaw_rate, max_yaw_rate);
+        }
+        self.direction_radians += yaw_rate * delta_time_s;
+
+        let forward = Vec2d::from_heading(self.direction_radians);
+        let right = forward.perp_right();
+        let max_forward = consts.max_forward_speed_ms;
+        let max_reverse = consts.max_reverse_speed_ms;
+        self.velocity = velocity.dot(forward).clamp(-max_reverse, max_forward);
+        // The rear tyres drag the car back in line, anything beyond their grip is a slide
+        let lateral = velocity.dot(right);
+        let max_correction = consts.rear_grip_mss * delta_time_s;
+        self.lateral_velocity = lateral - lateral.clamp(-max_correction, max_correction);
+        velocity = self.velocity_vector();
+        self.pos = self.pos + velocity * delta_time_s;
+    }
+
+    // Moves the car back to where it touched the wall and redirects it according to the track's
+    // collision response. Returns false if the car can't carry on
+    pub fn rebound(&mut self, track: &Track, contact: &WallContact) -> bool {
+        let velocity = self.velocity_vector();
+        let mut normal = (contact.wall.1 - contact.wall.0).normalize().perp_right();
+        if (self.previous_pos - contact.point).dot(normal) < 0.0 {
+            normal = -normal;
+        }
+        let into_wall = velocity.dot(normal).min(0.0);
+        let along_wall = velocity - normal * into_wall;
+        let new_velocity = match track.collision_response {
+            CollisionResponse::Crash => return false,
+            CollisionResponse::Bounce { restitution } => along_wall - normal * (into_wall * restitution),
+            CollisionResponse::Scrape { speed_penalty } => along_wall * (1.0 - speed_penalty),
+        };
+
+        // Back to the pose at the moment of impact, nudged off the wall
+        let toi = contact.time_of_impact;
+        let pos = self.previous_pos.lerp(self.pos, toi) + normal * REBOUND_SEPARATION_M;
+        self.pos = pos;
+        self.previous_pos = pos;
+        self.direction_radians = self.previous_direction_radians + (self.direction_radians - self.previous_direction_radians) * toi;
+        self.head_along(track, new_velocity);
+        true
+    }
+
+    // Sets the car moving at `new_velocity`. Neither physics model keeps a sideways speed for
+    // long, so the car is turned to point along it, or straight back along it if it's going
+    // backwards, unless that would put it through a wall
+    fn head_along(&mut self, track: &Track, new_velocity: Vec2d) {
+        let old_direction = self.direction_radians;
+        let forwards = new_velocity.dot(Vec2d::from_heading(old_direction)) >= 0.0;
+        self.direction_radians = match (new_velocity.length() > MIN_HEADING_SPEED_MS, forwards) {
+            (false, _) => old_direction,
+            (true, true) => new_velocity.heading(),
+            (true, false) => (-new_velocity).heading(),
+        };
+        if self.body().corners().iter().all(|corner| track.is_within_track(corner)) {
+            self.velocity = if forwards { new_velocity.length() } else { -new_velocity.length() };
+        } else {
+            self.direction_radians = old_direction;
+            self.velocity = new_velocity.dot(Vec2d::from_heading(old_direction));
+        }
+        self.previous_direction_radians = self.direction_radians;
+        self.lateral_velocity = 0.0;
+    }
+
+    // Moves the car by `offset`, stopping short of any wall in the way
+    fn push(&mut self, track: &Track, offset: Vec2d) {
+        let from = self.body();
+        let to = OrientedRect {
+            centre: self.pos + offset,
+            ..from
+        };
+        let allowed = match track.sweep(&from, &to) {
+            Some(contact) => (contact.time_of_impact * offset.length() - REBOUND_SEPARATION_M).max(0.0),
+            None => offset.length(),
+        };
+        if allowed > 0.0 {
+            self.pos = self.pos + offset.normalize() * allowed;
+        }
+    }
+
+    // Pushes two overlapping cars apart along `normal`, which points from this car to the other,
+    // and exchanges the speed they were closing with as equal masses would. Neither is pushed
+    // through a wall
+    pub fn bounce_off(&mut self, other: &mut Car, track: &Track, normal: Vec2d, depth: f32, restitution: f32) {
+        let separation = normal * (depth / 2.0 + REBOUND_SEPARATION_M);
+        self.push(track, -separation);
+        other.push(track, separation);
+
+        let (velocity, other_velocity) = (self.velocity_vector(), other.velocity_vector());
+        let closing = (other_velocity - velocity).dot(normal).min(0.0);
+        let impulse = normal * (-(1.0 + restitution) * closing / 2.0);
+        self.head_along(track, velocity - impulse);
+        other.head_along(track, other_velocity + impulse);
+    }
+
+    // Still waiting on the grid or racing, rather than parked after the end of its run
+    pub fn is_on_track(&self) -> bool {
+        self.state == CarState::StartLine || self.state == CarState::Racing
+    }
+
+    // Ends the run outside of `update_state`, after it has counted the tick as raced. As for any
+    // other end of a run, that tick belongs in `end_tick` rather than `ticks`
+    pub fn crash(&mut self, game_state: &mut CarProgress, game_time_s: f32) {
+        if self.state == CarState::Racing {
+            game_state.ticks = game_state.ticks.saturating_sub(1);
+        }
+        self.state = CarState::Crashed;
+        game_state.state = CarState::Crashed;
+        game_state.end_time = Some(game_time_s);
+        game_state.end_tick = Some(game_state.ticks + 1);
+    }
+
+    // `game_time_s` is the time at the start of the tick that took the car from its previous pose,
+    // end times are interpolated between that and the end of the tick
+    pub fn update_state(&mut self, track: &Track, game_state: &mut CarProgress, game_time_s: f32, delta_time_s: f32) {
+        if self.state == CarState::Racing {
+            let mut contact = track.sweep(&self.previous_body(), &self.body());
+            // Gates are crossed on the way to the wall, before the rebound puts the car back
+            let (moved_from, mut moved_to, mut moved_for) = (self.previous_pos, self.pos, 1.0);
+            if let Some(wall) = contact {
+                moved_to = self.previous_pos.lerp(self.pos, wall.time_of_impact);
+                moved_for = wall.time_of_impact;
+                if self.rebound(track, &wall) {
+                    game_state.wall_contact = Some(wall);
+                    game_state.wall_hits += 1;
+                    contact = None;
+                }
+            }
+            if contact.is_none() && track.is_within_track(&self.pos) {
+                let sectors = track.sectors_per_lap();
+                let last_gate = track.gate((game_state.next_gate + sectors - 1) % sectors);
+                if game_state.near_last_gate && last_gate.distance(&self.pos) > GATE_REARM_DISTANCE_M {
+                    game_state.near_last_gate = false;
+                }
+                // Several gates can be passed in one tick, but only in order along the move and
+                // each of them once. Without checkpoints the finish line is next as soon as it's
+                // passed, so backing over it and crossing it again doesn't count another lap
+                let mut passed_at = f32::NEG_INFINITY;
+                for _ in 0..sectors {
+                    if game_state.laps_completed >= track.laps() || (sectors == 1 && game_state.near_last_gate) {
+                        break;
                     }
-                } else {
+                    match track.gate(game_state.next_gate).crossing(&moved_from, &moved_to) {
+                        Some(t) if t > passed_at => {
+                            game_state.pass_gate(sectors, game_time_s + t * moved_for * delta_time_s);
+                            passed_at = t;
+                        }
+                        _ => break,
+                    }
+                }
+                if game_state.laps_completed >= track.laps() {
+                    game_state.end_time = game_state.last_gate_time;
+                    game_state.end_tick = Some(game_state.ticks + 1);
+                    self.state = CarState::Finished;
+                } else if track.termination_condition.is_timed_out(game_state.ticks, game_time_s - game_state.start_time) {
+                    self.state = CarState::TimedOut;
                     game_state.end_time = Some(game_time_s);
-                    self.state = CarState::Crashed
+                    game_state.end_tick = Some(game_state.ticks + 1);
+                } else {
+                    game_state.ticks += 1;
+                    self.state = CarState::Racing;
                 }
-                game_state.state = self.state;
+                game_state.measure(track, &self.pos);
+            } else {
+                let impact = match &contact {
+                    Some(wall) => wall.time_of_impact,
+                    None => track.exit_fraction(&self.previous_pos, &self.pos),
+                };
+                game_state.measure(track, &self.previous_pos.lerp(self.pos, impact));
+                game_state.end_time = Some(game_time_s + impact * delta_time_s);
+                game_state.end_tick = Some(game_state.ticks + 1);
+                game_state.wall_contact = contact;
+                self.state = CarState::Crashed
             }
-            _ => {}
+            game_state.state = self.state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    use crate::coordinates::{Boundary, LineType};
+    use crate::gate::{Gate, SegmentGate};
+    use crate::track::{ParallelRectSection, TrackMetadata};
+
+    fn open_ground(finish_line: Gate, checkpoints: Vec<Gate>, laps: u32) -> Track {
+        let ground = ParallelRectSection {
+            left_x: -100.0,
+            right_x: 100.0,
+            top_y: 200.0,
+            bottom_y: -100.0,
+        };
+        Track::new(
+            TrackMetadata::default(),
+            Vec2d::new(0.0, -5.0),
+            0.0,
+            finish_line,
+            vec![Box::new(ground)],
+            TerminationCondition::Seconds(1000.0),
+        )
+        .with_checkpoints(checkpoints)
+        .with_laps(laps)
+    }
+
+    // Moves the car straight from one point to another in a tick of 1s starting at `time_s`
+    fn hop(car: &mut Car, track: &Track, progress: &mut CarProgress, from: (f32, f32), to: (f32, f32), time_s: f32) {
+        car.previous_pos = Vec2d::new(from.0, from.1);
+        car.pos = Vec2d::new(to.0, to.1);
+        car.update_state(track, progress, time_s, 1.0);
+    }
+
+    fn racing_car(track: &Track) -> Car {
+        Car {
+            state: CarState::Racing,
+            ..Car::on_start_line(track, "car")
+        }
+    }
+
+    #[test]
+    fn laps_count_checkpoints_then_the_finish() {
+        let finish = SegmentGate::across(Vec2d::new(0.0, 0.0), 50.0, 0.0).into();
+        let checkpoint = SegmentGate::across(Vec2d::new(0.0, 100.0), 50.0, 0.0).into();
+        let track = open_ground(finish, vec![checkpoint], 2);
+        let (mut car, mut progress) = (racing_car(&track), CarProgress::new(0.0));
+
+        // The finish doesn't count before the checkpoint
+        hop(&mut car, &track, &mut progress, (0.0, -1.0), (0.0, 3.0), 0.0);
+        assert_eq!((progress.next_gate, progress.laps_completed), (0, 0));
+        // Passed a quarter of the way through the tick
+        hop(&mut car, &track, &mut progress, (0.0, 99.0), (0.0, 103.0), 10.0);
+        assert_eq!(progress.next_gate, 1);
+        assert_eq!(progress.sector_times, vec![10.25]);
+        hop(&mut car, &track, &mut progress, (0.0, -2.0), (0.0, 2.0), 20.0);
+        assert_eq!((progress.next_gate, progress.laps_completed), (0, 1));
+        assert_eq!(progress.lap_times, vec![20.5]);
+
+        // Going backwards through the checkpoint doesn't count
+        hop(&mut car, &track, &mut progress, (0.0, 101.0), (0.0, 99.0), 30.0);
+        assert_eq!(progress.next_gate, 0);
+        hop(&mut car, &track, &mut progress, (0.0, 99.0), (0.0, 101.0), 40.0);
+        hop(&mut car, &track, &mut progress, (0.0, -1.0), (0.0, 1.0), 50.0);
+        assert_eq!(progress.laps_completed, 2);
+        assert_eq!(progress.lap_times, vec![20.5, 30.0]);
+        assert_eq!(progress.sector_times, vec![10.25, 10.25, 20.0, 10.0]);
+        assert_eq!(progress.state, CarState::Finished);
+        assert_eq!(progress.end_time, Some(50.5));
+    }
+
+    #[test]
+    fn backing_over_the_finish_line_does_not_count_a_lap() {
+        let finish = Gate::Line(Boundary {
+            line_type: LineType::Horizontal(0.0),
+            positive_inf_within: false,
+        });
+        let track = open_ground(finish, Vec::new(), 2);
+        let (mut car, mut progress) = (racing_car(&track), CarProgress::new(0.0));
+
+        hop(&mut car, &track, &mut progress, (0.0, -1.0), (0.0, 1.0), 0.0);
+        assert_eq!(progress.laps_completed, 1);
+        // Back over the line and forwards over it again without going anywhere
+        hop(&mut car, &track, &mut progress, (0.0, 1.0), (0.0, -1.0), 1.0);
+        hop(&mut car, &track, &mut progress, (0.0, -1.0), (0.0, 1.0), 2.0);
+        assert_eq!(progress.laps_completed, 1);
+
+        // Once the car has been away from the line it counts again
+        hop(&mut car, &track, &mut progress, (0.0, 1.0), (0.0, -50.0), 3.0);
+        hop(&mut car, &track, &mut progress, (0.0, -1.0), (0.0, 1.0), 4.0);
+        assert_eq!(progress.laps_completed, 2);
+        assert_eq!(progress.state, CarState::Finished);
+    }
+
+    #[test]
+    fn cars_crashing_into_each_other_end_on_the_same_tick_as_a_wall_crash() {
+        let track = open_ground(SegmentGate::across(Vec2d::new(0.0, 150.0), 50.0, 0.0).into(), Vec::new(), 1);
+
+        let (mut walled, mut walled_progress) = (racing_car(&track), CarProgress::new(0.0));
+        hop(&mut walled, &track, &mut walled_progress, (0.0, 0.0), (0.0, 10.0), 0.0);
+        hop(&mut walled, &track, &mut walled_progress, (0.0, 10.0), (0.0, 210.0), 1.0);
+        assert_eq!(walled_progress.state, CarState::Crashed);
+
+        let (mut hit, mut hit_progress) = (racing_car(&track), CarProgress::new(0.0));
+        hop(&mut hit, &track, &mut hit_progress, (0.0, 0.0), (0.0, 10.0), 0.0);
+        hop(&mut hit, &track, &mut hit_progress, (0.0, 10.0), (0.0, 20.0), 1.0);
+        hit.crash(&mut hit_progress, 2.0);
+
+        assert_eq!(hit_progress.ticks, walled_progress.ticks);
+        assert_eq!(hit_progress.end_tick, walled_progress.end_tick);
+        assert_eq!(hit_progress.end_tick, Some(2));
+    }
+
+    #[test]
+    fn bounces_keep_the_sideways_part_of_the_impulse() {
+        let track = open_ground(SegmentGate::across(Vec2d::new(0.0, 150.0), 50.0, 0.0).into(), Vec::new(), 1);
+        let mut moving = Car {
+            velocity: 10.0,
+            ..racing_car(&track)
+        };
+        // Facing across the other car's path, overlapping its nose by half a metre
+        let mut standing = Car {
+            pos: moving.pos + Vec2d::new(0.0, (CAR_LENGTH_M + CAR_WIDTH_M) / 2.0 - 0.5),
+            direction_radians: FRAC_PI_2,
+            ..racing_car(&track)
+        };
+        moving.bounce_off(&mut standing, &track, Vec2d::new(0.0, 1.0), 0.5, 0.0);
+
+        assert!((moving.velocity_vector() - Vec2d::new(0.0, 5.0)).length() < 1e-4);
+        assert!((standing.velocity_vector() - Vec2d::new(0.0, 5.0)).length() < 1e-4);
+        assert!(standing.pos.y - moving.pos.y > (CAR_LENGTH_M + CAR_WIDTH_M) / 2.0);
+    }
+
+    #[test]
+    fn bounces_do_not_push_cars_through_walls() {
+        let mut track = open_ground(SegmentGate::across(Vec2d::new(0.0, 150.0), 50.0, 0.0).into(), Vec::new(), 1);
+        // A lane exactly two cars wide
+        track.set_sections(vec![Box::new(ParallelRectSection {
+            left_x: -2.0,
+            right_x: 2.0,
+            top_y: 200.0,
+            bottom_y: -100.0,
+        })]);
+        let mut left = Car {
+            pos: Vec2d::new(-0.95, 0.0),
+            ..racing_car(&track)
+        };
+        let mut right = Car {
+            pos: Vec2d::new(0.95, 0.0),
+            ..racing_car(&track)
+        };
+        left.bounce_off(&mut right, &track, Vec2d::new(1.0, 0.0), 0.1, 0.5);
+
+        for car in [&left, &right] {
+            assert!(car.body().corners().iter().all(|corner| track.is_within_track(corner)));
         }
+        assert!(right.pos.x - left.pos.x > 1.9);
+    }
+
+    // Drives up and to the right at 10 m/s into the top wall, whose normal is straight down
+    fn glance_off_the_top(response: CollisionResponse) -> (Car, CarProgress) {
+        let track = open_ground(SegmentGate::across(Vec2d::new(0.0, 0.0), 50.0, 0.0).into(), Vec::new(), 1)
+            .with_collision_response(response);
+        let mut car = Car {
+            velocity: 10.0,
+            direction_radians: FRAC_PI_4,
+            previous_direction_radians: FRAC_PI_4,
+            ..racing_car(&track)
+        };
+        let mut progress = CarProgress::new(0.0);
+        hop(&mut car, &track, &mut progress, (0.0, 195.0), (5.0, 200.0), 0.0);
+        (car, progress)
+    }
+
+    #[test]
+    fn bounces_scale_the_speed_into_the_wall_by_the_restitution() {
+        let (car, progress) = glance_off_the_top(CollisionResponse::Bounce { restitution: 0.5 });
+
+        let into_wall = 10.0 * FRAC_PI_4.cos();
+        assert!((car.velocity_vector() - Vec2d::new(into_wall, -0.5 * into_wall)).length() < 1e-4);
+        assert_eq!((progress.state, progress.wall_hits), (CarState::Racing, 1));
+    }
+
+    #[test]
+    fn scrapes_lose_the_speed_into_the_wall_and_the_penalty() {
+        let (car, progress) = glance_off_the_top(CollisionResponse::Scrape { speed_penalty: 0.2 });
+
+        let along_wall = 10.0 * FRAC_PI_4.cos();
+        assert!((car.velocity_vector() - Vec2d::new(0.8 * along_wall, 0.0)).length() < 1e-4);
+        assert_eq!((progress.state, progress.wall_hits), (CarState::Racing, 1));
+    }
+
+    #[test]
+    fn gates_crossed_on_the_way_into_a_wall_still_count() {
+        let finish = SegmentGate::across(Vec2d::new(0.0, 190.0), 50.0, 0.0).into();
+        let track = open_ground(finish, Vec::new(), 1).with_collision_response(CollisionResponse::Bounce { restitution: 0.5 });
+        let (mut car, mut progress) = (racing_car(&track), CarProgress::new(0.0));
+
+        // The nose reaches the wall at y = 200 five eighths of the way through the tick, with the
+        // centre over the finish line a quarter of the way through
+        hop(&mut car, &track, &mut progress, (0.0, 185.0), (0.0, 205.0), 0.0);
+        assert_eq!(progress.wall_hits, 1);
+        assert_eq!(progress.state, CarState::Finished);
+        assert!((progress.end_time.unwrap() - 0.25).abs() < 1e-4);
+    }
+
+    // Runs one bicycle tick of `delta_time_s` from rolling straight up at `speed` with no throttle
+    fn bicycle_tick(consts: &BicycleConstants, speed: f32, steering: f32, delta_time_s: f32) -> Car {
+        let mut car = Car {
+            velocity: speed,
+            ..Car::new(Vec2d::new(0.0, 0.0), "car")
+        };
+        let input = AnalogInput {
+            steering,
+            ..AnalogInput::default()
+        };
+        car.update_bicycle(consts, delta_time_s, input);
+        car
+    }
+
+    #[test]
+    fn bicycles_cannot_turn_standing_still() {
+        let car = bicycle_tick(&BicycleConstants::default(), 0.0, 1.0, 0.1);
+
+        assert_eq!(car.direction_radians, 0.0);
+        assert_eq!(car.pos, Vec2d::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn bicycles_turn_wider_the_faster_they_go() {
+        let consts = BicycleConstants::default();
+        let turn_radius = |speed: f32| {
+            let delta_time_s = 0.01;
+            speed / (bicycle_tick(&consts, speed, 0.5, delta_time_s).direction_radians / delta_time_s)
+        };
+
+        let radii = [turn_radius(5.0), turn_radius(10.0), turn_radius(20.0), turn_radius(40.0)];
+        assert!(radii.windows(2).all(|pair| pair[0] < pair[1]), "{radii:?}");
+    }
+
+    #[test]
+    fn front_grip_caps_the_yaw_rate() {
+        let consts = BicycleConstants::default();
+        let car = bicycle_tick(&consts, 20.0, 1.0, 0.1);
+        // Full lock would turn at 20 * tan(0.432) / 3 = 3.08 rad/s
+        assert!((car.direction_radians / 0.1 - consts.front_grip_mss / 20.0).abs() < 1e-4);
+
+        let grippy = BicycleConstants {
+            front_grip_mss: 1000.0,
+            ..consts
+        };
+        assert!(bicycle_tick(&grippy, 20.0, 1.0, 0.1).direction_radians / 0.1 > 3.0);
+    }
+
+    #[test]
+    fn rear_grip_lets_the_car_slide_at_the_limit() {
+        let consts = BicycleConstants::default();
+        // Turning right at 0.6 rad/s leaves about 1.2 m/s of the old velocity off to the left,
+        // more than the rear tyres can take out in a tick
+        let car = bicycle_tick(&consts, 20.0, 1.0, 0.1);
+        let sideways = 20.0 * (consts.front_grip_mss / 20.0 * 0.1).sin();
+        assert!((car.lateral_velocity + sideways - consts.rear_grip_mss * 0.1).abs() < 1e-3);
+
+        // A gentle turn stays within the grip and the car goes where it points
+        let car = bicycle_tick(&consts, 5.0, 0.1, 0.1);
+        assert!(car.direction_radians > 0.0);
+        assert_eq!(car.lateral_velocity, 0.0);
     }
 }
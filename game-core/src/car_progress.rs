@@ -1,11 +1,45 @@
+use serde::{Deserialize, Serialize};
+
 use crate::car::CarState;
+use crate::collision::{CarContact, WallContact};
+use crate::coordinates::Vec2d;
+use crate::track::Track;
+
+// A car has to get this far from the gate it last passed before passing it again counts, which
+// matters on tracks with no checkpoints where the finish line is passed lap after lap
+pub const GATE_REARM_DISTANCE_M: f32 = 20.0;
 
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct CarProgress {
     pub ticks: u64,
     pub start_time: f32,
+    // When the car finished or crashed, interpolated to the moment within the final tick it
+    // crossed the line or hit the wall
     pub end_time: Option<f32>,
+    // Number of ticks raced, counting the one the run ended in
+    pub end_tick: Option<u64>,
     pub state: CarState,
+    // The last wall the car touched, which ended the run if the track's response is to crash
+    pub wall_contact: Option<WallContact>,
+    pub wall_hits: u32,
+    // Every time another car was run into, when car collisions are enabled
+    pub car_contacts: Vec<CarContact>,
+    // Index of the gate the car has to pass next, see `Track::gate`
+    pub next_gate: usize,
+    pub laps_completed: u32,
+    pub lap_times: Vec<f32>,
+    // Time taken over every sector passed so far, across all laps
+    pub sector_times: Vec<f32>,
+    pub last_gate_time: Option<f32>,
+    // Whether the car is still within GATE_REARM_DISTANCE_M of the gate it last passed
+    #[serde(default)]
+    pub near_last_gate: bool,
+    // Distance along the racing line covered so far, see `Track::race_distance`
+    pub distance_m: f32,
+    // `distance_m` as a fraction of the whole race, 1 once finished
+    pub completion: f32,
+    // Furthest the car got, which is what a car that didn't finish is classified on
+    pub best_distance_m: f32,
 }
 
 impl CarProgress {
@@ -16,5 +50,34 @@ impl CarProgress {
         }
     }
 
+    // Updates how far round the race the car is from where it is now
+    pub fn measure(&mut self, track: &Track, pos: &Vec2d) {
+        let race_length = track.race_length();
+        self.distance_m = if self.laps_completed >= track.laps() {
+            race_length
+        } else {
+            track.race_distance(pos, self.laps_completed, self.next_gate)
+        };
+        self.best_distance_m = self.best_distance_m.max(self.distance_m);
+        self.completion = if race_length > 0.0 {
+            (self.distance_m / race_length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+    }
 
+    // Records the split for the gate the car has just passed and moves on to the next one
+    pub fn pass_gate(&mut self, sectors_per_lap: usize, crossing_time_s: f32) {
+        let sector_start = self.last_gate_time.unwrap_or(self.start_time);
+        self.sector_times.push(crossing_time_s - sector_start);
+        self.last_gate_time = Some(crossing_time_s);
+        self.near_last_gate = true;
+        self.next_gate += 1;
+        if self.next_gate == sectors_per_lap {
+            let lap_start = self.lap_times.iter().sum::<f32>() + self.start_time;
+            self.lap_times.push(crossing_time_s - lap_start);
+            self.laps_completed += 1;
+            self.next_gate = 0;
+        }
+    }
 }
@@ -0,0 +1,442 @@
+use serde::{Deserialize, Serialize};
+
+use crate::car::TerminationCondition;
+use crate::coordinates::{bounding_box, Vec2d};
+use crate::gate::SegmentGate;
+use crate::track::{
+    GridSlot, Track, TrackMetadata, TrackSection, GRID_COLUMN_SPACING_M, GRID_ROW_SPACING_M, GRID_SLOTS,
+};
+
+// Number of straight pieces each spline segment is flattened into
+pub const SAMPLES_PER_SEGMENT: usize = 16;
+// How far from the ends of an open centerline the start and finish are placed, roughly a car length
+pub const START_OFFSET_M: f32 = 5.0;
+// Checkpoints put round a closed loop so a lap can't be cut short by reversing over the line
+pub const LOOP_CHECKPOINTS: usize = 3;
+
+// Written as a plain string, as RON can't read a bare variant name inside the tagged `SectionSpec`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum SplineKind {
+    // The curve passes through every control point
+    #[default]
+    CatmullRom,
+    // Points go anchor, handle, handle, anchor, handle, handle, anchor, ... A closed loop leaves
+    // off the final anchor as it is the same as the first
+    CubicBezier,
+}
+
+impl From<SplineKind> for String {
+    fn from(kind: SplineKind) -> String {
+        format!("{:?}", kind)
+    }
+}
+
+impl TryFrom<String> for SplineKind {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<SplineKind, String> {
+        match name.as_str() {
+            "CatmullRom" => Ok(SplineKind::CatmullRom),
+            "CubicBezier" => Ok(SplineKind::CubicBezier),
+            _ => Err(format!("unknown spline kind {:?}, expected CatmullRom or CubicBezier", name)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ControlPoint {
+    pub pos: Vec2d,
+    // Full width of the road at this point
+    pub width: f32,
+}
+
+impl ControlPoint {
+    pub fn new(x: f32, y: f32, width: f32) -> ControlPoint {
+        ControlPoint {
+            pos: Vec2d::new(x, y),
+            width,
+        }
+    }
+
+    fn weighted(points: [ControlPoint; 4], weights: [f32; 4]) -> ControlPoint {
+        let mut pos = Vec2d::default();
+        let mut width = 0.0;
+        for (point, weight) in points.iter().zip(weights) {
+            pos = pos + point.pos * weight;
+            width += point.width * weight;
+        }
+        ControlPoint {
+            pos,
+            width: width.max(0.0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Centerline {
+    #[serde(default)]
+    pub kind: SplineKind,
+    #[serde(default)]
+    pub closed: bool,
+    pub points: Vec<ControlPoint>,
+}
+
+impl Centerline {
+    pub fn catmull_rom(points: Vec<ControlPoint>, closed: bool) -> Centerline {
+        Centerline {
+            kind: SplineKind::CatmullRom,
+            closed,
+            points,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        let n = self.points.len();
+        let enough_points = match (self.kind, self.closed) {
+            (SplineKind::CatmullRom, false) => n >= 2,
+            (SplineKind::CatmullRom, true) => n >= 3,
+            (SplineKind::CubicBezier, false) => n >= 4 && (n - 1).is_multiple_of(3),
+            (SplineKind::CubicBezier, true) => n >= 3 && n.is_multiple_of(3),
+        };
+        if !enough_points {
+            return Err(format!(
+                "{} {:?} centerline can't be made from {} control points",
+                if self.closed { "closed" } else { "open" },
+                self.kind,
+                n
+            ));
+        }
+        match self.points.iter().position(|p| p.width <= 0.0 || !p.width.is_finite()) {
+            Some(i) => Err(format!("control point {} has a non-positive width", i)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn segment_count(&self) -> usize {
+        let n = self.points.len();
+        match (self.kind, self.closed) {
+            (SplineKind::CatmullRom, false) => n.saturating_sub(1),
+            (SplineKind::CatmullRom, true) => n,
+            (SplineKind::CubicBezier, false) => n.saturating_sub(1) / 3,
+            (SplineKind::CubicBezier, true) => n / 3,
+        }
+    }
+
+    fn point(&self, index: isize) -> ControlPoint {
+        let n = self.points.len() as isize;
+        let index = if self.closed {
+            index.rem_euclid(n)
+        } else {
+            index.clamp(0, n - 1)
+        };
+        self.points[index as usize]
+    }
+
+    // `t` runs from 0 to 1 along the given segment
+    pub fn evaluate(&self, segment: usize, t: f32) -> ControlPoint {
+        let i = segment as isize;
+        match self.kind {
+            SplineKind::CatmullRom => {
+                let (t2, t3) = (t * t, t * t * t);
+                ControlPoint::weighted(
+                    [self.point(i - 1), self.point(i), self.point(i + 1), self.point(i + 2)],
+                    [
+                        0.5 * (-t + 2.0 * t2 - t3),
+                        0.5 * (2.0 - 5.0 * t2 + 3.0 * t3),
+                        0.5 * (t + 4.0 * t2 - 3.0 * t3),
+                        0.5 * (-t2 + t3),
+                    ],
+                )
+            }
+            SplineKind::CubicBezier => {
+                let s = 1.0 - t;
+                ControlPoint::weighted(
+                    [self.point(3 * i), self.point(3 * i + 1), self.point(3 * i + 2), self.point(3 * i + 3)],
+                    [s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t],
+                )
+            }
+        }
+    }
+
+    // Flattens the spline into points spaced along it. A closed loop doesn't repeat its first point
+    pub fn sample(&self, samples_per_segment: usize) -> Vec<CenterlineSample> {
+        let segments = self.segment_count();
+        let mut samples: Vec<CenterlineSample> = Vec::with_capacity(segments * samples_per_segment + 1);
+        for segment in 0..segments {
+            for step in 0..samples_per_segment {
+                let point = self.evaluate(segment, step as f32 / samples_per_segment as f32);
+                samples.push(CenterlineSample::after(samples.last(), point));
+            }
+        }
+        if !self.closed && segments > 0 {
+            let point = self.evaluate(segments - 1, 1.0);
+            samples.push(CenterlineSample::after(samples.last(), point));
+        }
+        samples
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CenterlineSample {
+    pub pos: Vec2d,
+    pub width: f32,
+    // Arc length from the first sample
+    pub distance: f32,
+}
+
+impl CenterlineSample {
+    fn after(previous: Option<&CenterlineSample>, point: ControlPoint) -> CenterlineSample {
+        let distance = previous.map_or(0.0, |p| p.distance + p.pos.distance(point.pos));
+        CenterlineSample {
+            pos: point.pos,
+            width: point.width,
+            distance,
+        }
+    }
+}
+
+// A whole road described by its centerline. The spline is flattened once up front so queries
+// only deal with straight pieces
+#[derive(Clone, Debug)]
+pub struct CenterlineSection {
+    pub centerline: Centerline,
+    samples: Vec<CenterlineSample>,
+    length: f32,
+}
+
+impl CenterlineSection {
+    pub fn new(centerline: Centerline) -> CenterlineSection {
+        let samples = centerline.sample(SAMPLES_PER_SEGMENT);
+        let mut length = samples.last().map_or(0.0, |s| s.distance);
+        if centerline.closed {
+            if let (Some(first), Some(last)) = (samples.first(), samples.last()) {
+                length += last.pos.distance(first.pos);
+            }
+        }
+        CenterlineSection {
+            centerline,
+            samples,
+            length,
+        }
+    }
+
+    pub fn samples(&self) -> &[CenterlineSample] {
+        &self.samples
+    }
+
+    // Total arc length, including the closing piece of a loop
+    pub fn length(&self) -> f32 {
+        self.length
+    }
+
+    // Consecutive pairs of samples, wrapping round for a closed loop
+    pub fn pieces(&self) -> impl Iterator<Item = (&CenterlineSample, &CenterlineSample)> + '_ {
+        let n = self.samples.len();
+        let count = if self.centerline.closed { n } else { n.saturating_sub(1) };
+        (0..count).map(move |i| (&self.samples[i], &self.samples[(i + 1) % n]))
+    }
+
+    // The piece a distance along the centerline falls on and how far along it
+    fn piece_at(&self, distance: f32) -> Option<(&CenterlineSample, &CenterlineSample, f32)> {
+        let distance = if self.centerline.closed {
+            distance.rem_euclid(self.length)
+        } else {
+            distance.clamp(0.0, self.length)
+        };
+        let mut last = None;
+        for (a, b) in self.pieces() {
+            let piece_length = a.pos.distance(b.pos);
+            let piece_end = a.distance + piece_length;
+            last = Some((a, b, 1.0));
+            if distance <= piece_end && piece_length > 0.0 {
+                return Some((a, b, (distance - a.distance) / piece_length));
+            }
+        }
+        last
+    }
+
+    // Position and heading (in the car's convention) at a distance along the centerline
+    pub fn pose_at(&self, distance: f32) -> (Vec2d, f32) {
+        match self.piece_at(distance) {
+            Some((a, b, t)) => (a.pos.lerp(b.pos, t), (b.pos - a.pos).heading()),
+            None => (self.samples.first().map_or(Vec2d::default(), |s| s.pos), 0.0),
+        }
+    }
+
+    pub fn width_at(&self, distance: f32) -> f32 {
+        match self.piece_at(distance) {
+            Some((a, b, t)) => a.width + (b.width - a.width) * t,
+            None => self.samples.first().map_or(0.0, |s| s.width),
+        }
+    }
+
+    // Arc length of the point on the centerline closest to `pos`
+    pub fn project(&self, pos: &Vec2d) -> f32 {
+        let mut best = (f32::MAX, 0.0);
+        for (a, b) in self.pieces() {
+            let (point, t) = nearest_on_piece(a.pos, b.pos, pos);
+            let gap = pos.distance(point);
+            if gap < best.0 {
+                best = (gap, a.distance + t * a.pos.distance(b.pos));
+            }
+        }
+        best.1
+    }
+
+    // Up to `count` slots of a staggered grid going back along the road from the pole at
+    // `pole_distance`, leaving out any a car wouldn't fit on the road in
+    pub fn grid_behind(&self, pole_distance: f32, count: usize) -> Vec<GridSlot> {
+        (0..4 * count)
+            .filter_map(|i| {
+                let (row, column) = ((i / 2) as f32, (i % 2) as f32);
+                let distance = pole_distance - (row + column / 2.0) * GRID_ROW_SPACING_M;
+                // Going back more than half way round a loop would meet the front of the grid
+                let room = if self.centerline.closed { self.length / 2.0 } else { pole_distance };
+                if pole_distance - distance > room {
+                    return None;
+                }
+                let (pos, direction_radians) = self.pose_at(distance);
+                let right = Vec2d::from_heading(direction_radians).perp_right();
+                let slot = GridSlot {
+                    pos: pos + right * (column * GRID_COLUMN_SPACING_M),
+                    direction_radians,
+                };
+                slot.body().corners().iter().all(|corner| self.is_within(corner)).then_some(slot)
+            })
+            .take(count)
+            .collect()
+    }
+
+    // A gate across the whole width of the road
+    pub fn gate_at(&self, distance: f32) -> SegmentGate {
+        let (pos, direction) = self.pose_at(distance);
+        SegmentGate::across(pos, self.width_at(distance), direction)
+    }
+
+    fn tangent(&self, index: usize) -> Vec2d {
+        let n = self.samples.len();
+        let (before, after) = if self.centerline.closed {
+            ((index + n - 1) % n, (index + 1) % n)
+        } else {
+            (index.saturating_sub(1), (index + 1).min(n - 1))
+        };
+        (self.samples[after].pos - self.samples[before].pos).normalize()
+    }
+
+    // The left and right kerbs, one point per sample
+    pub fn kerbs(&self) -> (Vec<Vec2d>, Vec<Vec2d>) {
+        (0..self.samples.len())
+            .map(|i| {
+                let sample = &self.samples[i];
+                let offset = self.tangent(i).perp_right() * (sample.width / 2.0);
+                (sample.pos - offset, sample.pos + offset)
+            })
+            .unzip()
+    }
+}
+
+// The point on the piece a -> b closest to `pos` and how far along the piece it is
+pub fn nearest_on_piece(a: Vec2d, b: Vec2d, pos: &Vec2d) -> (Vec2d, f32) {
+    let along = b - a;
+    let len_sq = along.dot(along);
+    let t = if len_sq > 0.0 {
+        ((*pos - a).dot(along) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (a.lerp(b, t), t)
+}
+
+impl TrackSection for CenterlineSection {
+    fn is_within(&self, pos: &Vec2d) -> bool {
+        self.pieces().any(|(a, b)| {
+            let (nearest, t) = nearest_on_piece(a.pos, b.pos, pos);
+            let half_width = (a.width + (b.width - a.width) * t) / 2.0;
+            pos.distance(nearest) < half_width
+        })
+    }
+
+    fn edges(&self) -> Vec<(Vec2d, Vec2d)> {
+        let (left, right) = self.kerbs();
+        let n = left.len();
+        let count = if self.centerline.closed { n } else { n.saturating_sub(1) };
+        let mut vec = Vec::with_capacity(2 * count + 2);
+        for kerb in [&left, &right] {
+            vec.extend((0..count).map(|i| (kerb[i], kerb[(i + 1) % n])));
+        }
+        if !self.centerline.closed && n > 0 {
+            vec.push((left[0], right[0]));
+            vec.push((right[n - 1], left[n - 1]));
+        }
+        vec
+    }
+
+    // The road is every point within half a width of the centerline, which reaches past the
+    // kerbs round the outside of bends and at the ends
+    fn bounds(&self) -> Option<(Vec2d, Vec2d)> {
+        let half_width = self.samples.iter().map(|s| s.width / 2.0).fold(0.0, f32::max);
+        let pad = Vec2d::new(half_width, half_width);
+        let (min, max) = bounding_box(self.samples.iter().map(|s| s.pos))?;
+        Some((min - pad, max + pad))
+    }
+
+    fn centerline(&self) -> Option<&CenterlineSection> {
+        Some(self)
+    }
+}
+
+impl Track {
+    // Builds a track from a single centerline, with a grid laid along the road behind the start.
+    // An open road finishes a car length in from its end and starts far enough in from the other
+    // for the whole grid to fit behind. A closed loop starts just behind its start/finish line and
+    // has checkpoints spread evenly round it
+    pub fn from_centerline(
+        centerline: Centerline,
+        metadata: TrackMetadata,
+        termination_condition: TerminationCondition,
+    ) -> Track {
+        let section = CenterlineSection::new(centerline);
+        let (start_distance, finish_distance) = if section.centerline.closed {
+            (-START_OFFSET_M, 0.0)
+        } else {
+            (grid_start(&section), section.length() - START_OFFSET_M)
+        };
+        let grid = section.grid_behind(start_distance, GRID_SLOTS);
+        let (start, start_direction_radians) = section.pose_at(start_distance);
+        let checkpoints = if section.centerline.closed {
+            (1..=LOOP_CHECKPOINTS)
+                .map(|i| section.gate_at(section.length() * i as f32 / (LOOP_CHECKPOINTS + 1) as f32).into())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let finish_line = section.gate_at(finish_distance).into();
+        Track::new(
+            metadata,
+            start,
+            start_direction_radians,
+            finish_line,
+            vec![Box::new(section)],
+            termination_condition,
+        )
+        .with_checkpoints(checkpoints)
+        .with_grid(grid)
+    }
+}
+
+// How far along an open road the pole has to be for a full grid to fit behind it, moving up half
+// a row at a time. Never more than a third of the way along, with however many slots fit there
+fn grid_start(section: &CenterlineSection) -> f32 {
+    let mut best = (0, START_OFFSET_M);
+    let mut distance = START_OFFSET_M;
+    while best.0 < GRID_SLOTS && distance <= section.length() / 3.0 {
+        let slots = section.grid_behind(distance, GRID_SLOTS).len();
+        if slots > best.0 {
+            best = (slots, distance);
+        }
+        distance += GRID_ROW_SPACING_M / 2.0;
+    }
+    best.1
+}
+
@@ -0,0 +1,327 @@
+use serde::{Deserialize, Serialize};
+
+use crate::coordinates::{segment_intersection, Vec2d};
+use crate::spatial_index::IndexedEdge;
+use crate::track::Track;
+
+// How far either side of an edge we look to decide whether it is a real wall or just the seam
+// between two overlapping or touching sections
+const WALL_PROBE_M: f32 = 1e-3;
+// Halvings used to find where a point left the track or two cars met, plenty for the distance
+// moved in a tick
+const EXIT_BISECTION_STEPS: u32 = 16;
+
+// A rectangle with its length running along `direction_radians`, used for car bodies
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OrientedRect {
+    pub centre: Vec2d,
+    pub width: f32,
+    pub length: f32,
+    pub direction_radians: f32,
+}
+
+impl OrientedRect {
+    fn half_extents(&self) -> Vec2d {
+        Vec2d::new(self.width / 2.0, self.length / 2.0)
+    }
+
+    // Front left, front right, back right, back left
+    pub fn corners(&self) -> [Vec2d; 4] {
+        [(-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)].map(|(x, y)| {
+            let half = self.half_extents();
+            self.to_world(Vec2d::new(x * half.x, y * half.y))
+        })
+    }
+
+    // x is to the right of the rectangle, y is along its length
+    pub fn to_local(&self, point: Vec2d) -> Vec2d {
+        let forward = Vec2d::from_heading(self.direction_radians);
+        let offset = point - self.centre;
+        Vec2d::new(offset.dot(forward.perp_right()), offset.dot(forward))
+    }
+
+    pub fn to_world(&self, local: Vec2d) -> Vec2d {
+        let forward = Vec2d::from_heading(self.direction_radians);
+        self.centre + forward.perp_right() * local.x + forward * local.y
+    }
+
+    pub fn contains(&self, point: Vec2d) -> bool {
+        let local = self.to_local(point);
+        let half = self.half_extents();
+        local.x.abs() < half.x && local.y.abs() < half.y
+    }
+
+    // Separating axis test against another rectangle. If they overlap, gives the direction from
+    // this rectangle towards the other that separates them quickest and how far they overlap by
+    pub fn overlap(&self, other: &OrientedRect) -> Option<(Vec2d, f32)> {
+        let (corners, other_corners) = (self.corners(), other.corners());
+        let axes = [self.direction_radians, other.direction_radians].map(Vec2d::from_heading);
+        let mut best: Option<(Vec2d, f32)> = None;
+        for axis in axes.iter().flat_map(|forward| [*forward, forward.perp_right()]) {
+            let project = |points: &[Vec2d; 4]| {
+                points.iter().fold((f32::MAX, f32::MIN), |(min, max), p| (min.min(p.dot(axis)), max.max(p.dot(axis))))
+            };
+            let ((min, max), (other_min, other_max)) = (project(&corners), project(&other_corners));
+            let depth = max.min(other_max) - min.max(other_min);
+            if depth <= 0.0 {
+                return None;
+            }
+            if best.is_none_or(|(_, best_depth)| depth < best_depth) {
+                best = Some((axis, depth));
+            }
+        }
+        best.map(|(axis, depth)| {
+            if (other.centre - self.centre).dot(axis) < 0.0 {
+                (-axis, depth)
+            } else {
+                (axis, depth)
+            }
+        })
+    }
+
+    // The pose `t` of the way from this one to `to`
+    pub fn lerp(&self, to: &OrientedRect, t: f32) -> OrientedRect {
+        OrientedRect {
+            centre: self.centre.lerp(to.centre, t),
+            direction_radians: self.direction_radians + (to.direction_radians - self.direction_radians) * t,
+            ..*self
+        }
+    }
+
+    fn local_edges(&self) -> [(Vec2d, Vec2d); 4] {
+        let half = self.half_extents();
+        let corners = [(-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)].map(|(x, y)| Vec2d::new(x * half.x, y * half.y));
+        [0, 1, 2, 3].map(|i| (corners[i], corners[(i + 1) % 4]))
+    }
+}
+
+// What happens to a car that touches a wall
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CollisionResponse {
+    // The run is over
+    #[default]
+    Crash,
+    // The car is reflected off the wall, keeping `restitution` of the speed it hit it with
+    Bounce { restitution: f32 },
+    // The car loses the speed it hit the wall with and `speed_penalty` of what's left
+    Scrape { speed_penalty: f32 },
+}
+
+// How far through a tick two bodies that overlap at the end of it first touched, each moving in a
+// straight line from its `from` pose to its `to` pose
+pub fn first_overlap_fraction(a: (&OrientedRect, &OrientedRect), b: (&OrientedRect, &OrientedRect)) -> f32 {
+    let overlap_at = |t: f32| a.0.lerp(a.1, t).overlap(&b.0.lerp(b.1, t)).is_some();
+    if overlap_at(0.0) {
+        return 0.0;
+    }
+    let (mut apart, mut touching) = (0.0, 1.0);
+    for _ in 0..EXIT_BISECTION_STEPS {
+        let mid = (apart + touching) / 2.0;
+        if overlap_at(mid) {
+            touching = mid;
+        } else {
+            apart = mid;
+        }
+    }
+    touching
+}
+
+impl CollisionResponse {
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            CollisionResponse::Crash => Ok(()),
+            CollisionResponse::Bounce { restitution } if (0.0..=1.0).contains(restitution) => Ok(()),
+            CollisionResponse::Scrape { speed_penalty } if (0.0..=1.0).contains(speed_penalty) => Ok(()),
+            other => Err(format!("{:?} needs a coefficient between 0 and 1", other)),
+        }
+    }
+}
+
+// What happens when two cars touch
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CarCollisionResponse {
+    // The cars pass through each other, but the contact is still recorded
+    #[default]
+    Ghost,
+    // The cars are pushed apart and keep `restitution` of the speed they closed with
+    Bounce { restitution: f32 },
+    // Both cars are out of the race
+    CrashBoth,
+}
+
+impl CarCollisionResponse {
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            CarCollisionResponse::Bounce { restitution } if !(0.0..=1.0).contains(restitution) => {
+                Err(format!("{:?} needs a coefficient between 0 and 1", self))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CarContact {
+    // Tick of the race the cars first touched on
+    pub tick: u64,
+    // Label of the other car
+    pub other: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WallContact {
+    pub point: Vec2d,
+    // Fraction of the way through the tick, 0 is the previous pose and 1 the current one
+    pub time_of_impact: f32,
+    pub wall: (Vec2d, Vec2d),
+    // Index into `Track::sections` of the section the wall belongs to
+    pub section: usize,
+}
+
+impl Track {
+    // True if the edge really separates track from off-track at `point`, rather than being the
+    // join between two sections
+    pub fn is_wall_at(&self, point: Vec2d, edge: (Vec2d, Vec2d)) -> bool {
+        let normal = (edge.1 - edge.0).normalize().perp_right() * WALL_PROBE_M;
+        !(self.is_within_track(&(point + normal)) && self.is_within_track(&(point - normal)))
+    }
+
+    // How far along the move from `from` to `to` the point first left the track
+    pub fn exit_fraction(&self, from: &Vec2d, to: &Vec2d) -> f32 {
+        if !self.is_within_track(from) {
+            return 0.0;
+        }
+        let (mut inside, mut outside) = (0.0, 1.0);
+        for _ in 0..EXIT_BISECTION_STEPS {
+            let mid = (inside + outside) / 2.0;
+            if self.is_within_track(&from.lerp(*to, mid)) {
+                inside = mid;
+            } else {
+                outside = mid;
+            }
+        }
+        outside
+    }
+
+    fn is_wall_end(&self, point: Vec2d) -> bool {
+        (0..8).any(|i| {
+            let probe = point + Vec2d::from_heading(i as f32 * std::f32::consts::FRAC_PI_4) * WALL_PROBE_M;
+            !self.is_within_track(&probe)
+        })
+    }
+
+    // Moves the body from one pose to the other in a straight line and reports the first wall it
+    // touches. Rotation is interpolated corner by corner, which is plenty for a single tick
+    pub fn sweep(&self, from: &OrientedRect, to: &OrientedRect) -> Option<WallContact> {
+        let from_corners = from.corners();
+        let to_corners = to.corners();
+        let (mut min, mut max) = (from_corners[0], from_corners[0]);
+        for corner in from_corners.iter().chain(to_corners.iter()) {
+            min = Vec2d::new(min.x.min(corner.x), min.y.min(corner.y));
+            max = Vec2d::new(max.x.max(corner.x), max.y.max(corner.y));
+        }
+
+        let mut best: Option<WallContact> = None;
+        for &IndexedEdge { section, edge: wall } in self.spatial_index().edges_near(min, max) {
+            if wall.0.x.max(wall.1.x) < min.x
+                || wall.0.x.min(wall.1.x) > max.x
+                || wall.0.y.max(wall.1.y) < min.y
+                || wall.0.y.min(wall.1.y) > max.y
+            {
+                continue;
+            }
+            let mut consider = |time_of_impact: f32, point: Vec2d, is_wall: &dyn Fn() -> bool| {
+                if best.is_none_or(|b| time_of_impact < b.time_of_impact) && is_wall() {
+                    best = Some(WallContact {
+                        point,
+                        time_of_impact,
+                        wall,
+                        section,
+                    });
+                }
+            };
+            // Corners of the car running into the wall
+            for (start, end) in from_corners.iter().zip(to_corners.iter()) {
+                if let Some((t, u)) = segment_intersection(*start, *end, wall.0, wall.1) {
+                    let point = wall.0.lerp(wall.1, u);
+                    consider(t, point, &|| self.is_wall_at(point, wall));
+                }
+            }
+            // Ends of the wall running into the sides of the car, seen from the car
+            for point in [wall.0, wall.1] {
+                let (start, end) = (from.to_local(point), to.to_local(point));
+                for side in from.local_edges() {
+                    if let Some((t, _)) = segment_intersection(start, end, side.0, side.1) {
+                        consider(t, point, &|| self.is_wall_end(point));
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::TerminationCondition;
+    use crate::gate::SegmentGate;
+    use crate::track::{ParallelRectSection, TrackMetadata};
+
+    // Two pieces of road one above the other, `gap` apart
+    fn stacked(gap: f32) -> Track {
+        let (near, far) = (
+            ParallelRectSection {
+                left_x: -50.0,
+                right_x: 50.0,
+                top_y: 100.0,
+                bottom_y: -10.0,
+            },
+            ParallelRectSection {
+                left_x: -50.0,
+                right_x: 50.0,
+                top_y: 300.0,
+                bottom_y: 100.0 + gap,
+            },
+        );
+        Track::new(
+            TrackMetadata::default(),
+            Vec2d::new(0.0, 0.0),
+            0.0,
+            SegmentGate::across(Vec2d::new(0.0, 250.0), 100.0, 0.0).into(),
+            vec![Box::new(near), Box::new(far)],
+            TerminationCondition::Seconds(60.0),
+        )
+    }
+
+    fn car_at(y: f32) -> OrientedRect {
+        OrientedRect {
+            centre: Vec2d::new(0.0, y),
+            width: 2.0,
+            length: 5.0,
+            direction_radians: 0.0,
+        }
+    }
+
+    #[test]
+    fn fast_cars_hit_thin_walls() {
+        let track = stacked(0.5);
+        // Both poses are on the road, the wall is passed in between
+        let (from, to) = (car_at(90.0), car_at(110.0));
+        assert!(track.is_within_track(&from.centre) && track.is_within_track(&to.centre));
+
+        let contact = track.sweep(&from, &to).expect("the wall is in the way");
+        // The nose starts 7.5 m short of the wall and moves 20 m
+        assert!((contact.time_of_impact - 0.375).abs() < 1e-4, "{}", contact.time_of_impact);
+        assert!((contact.point.y - 100.0).abs() < 1e-3 && contact.point.x.abs() <= 1.0 + 1e-3);
+        assert_eq!(contact.section, 0);
+        assert_eq!(contact.wall.0.y, 100.0);
+        assert_eq!(contact.wall.1.y, 100.0);
+    }
+
+    #[test]
+    fn joins_between_sections_are_not_walls() {
+        let track = stacked(-10.0);
+        assert_eq!(track.sweep(&car_at(90.0), &car_at(110.0)), None);
+    }
+}
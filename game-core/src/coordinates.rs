@@ -1,12 +1,123 @@
-#[derive(Copy, Clone, Debug, Default)]
+use std::ops::{Add, Mul, Neg, Sub};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Vec2d {
     pub x: f32,
     pub y: f32,
 }
 impl Vec2d {
+    pub fn new(x: f32, y: f32) -> Vec2d {
+        Vec2d { x, y }
+    }
+
+    // Unit vector using the same convention as `Car::direction_radians`: 0 points up, pi/2 points right
+    pub fn from_heading(radians: f32) -> Vec2d {
+        Vec2d::new(radians.sin(), radians.cos())
+    }
+
+    // Inverse of `from_heading`, in (-pi, pi]
+    pub fn heading(&self) -> f32 {
+        self.x.atan2(self.y)
+    }
+
     pub fn distance(&self, other: Vec2d) -> f32 {
         ((self.x - other.x).powi(2) + (self.y - other.y).powi(2)).sqrt()
     }
+
+    pub fn length(&self) -> f32 {
+        self.dot(*self).sqrt()
+    }
+
+    pub fn dot(&self, other: Vec2d) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
+    // z component of the 3d cross product, positive when `other` is anticlockwise of `self`
+    pub fn cross(&self, other: Vec2d) -> f32 {
+        self.x * other.y - self.y * other.x
+    }
+
+    pub fn normalize(&self) -> Vec2d {
+        let len = self.length();
+        if len > 0.0 {
+            *self * (1.0 / len)
+        } else {
+            *self
+        }
+    }
+
+    // Rotated 90 degrees clockwise, so the right hand side when facing along `self`
+    pub fn perp_right(&self) -> Vec2d {
+        Vec2d::new(self.y, -self.x)
+    }
+
+    pub fn lerp(&self, other: Vec2d, t: f32) -> Vec2d {
+        *self + (other - *self) * t
+    }
+}
+
+impl Add for Vec2d {
+    type Output = Vec2d;
+
+    fn add(self, rhs: Vec2d) -> Vec2d {
+        Vec2d::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl Sub for Vec2d {
+    type Output = Vec2d;
+
+    fn sub(self, rhs: Vec2d) -> Vec2d {
+        Vec2d::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl Mul<f32> for Vec2d {
+    type Output = Vec2d;
+
+    fn mul(self, rhs: f32) -> Vec2d {
+        Vec2d::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+impl Neg for Vec2d {
+    type Output = Vec2d;
+
+    fn neg(self) -> Vec2d {
+        Vec2d::new(-self.x, -self.y)
+    }
+}
+
+// Where the segments p1 -> p2 and q1 -> q2 cross, as fractions along each of them
+pub fn segment_intersection(p1: Vec2d, p2: Vec2d, q1: Vec2d, q2: Vec2d) -> Option<(f32, f32)> {
+    let r = p2 - p1;
+    let s = q2 - q1;
+    let denominator = r.cross(s);
+    if denominator.abs() < f32::EPSILON {
+        // Parallel or degenerate, a glancing touch along a wall isn't treated as a hit
+        return None;
+    }
+    let offset = q1 - p1;
+    let t = offset.cross(s) / denominator;
+    let u = offset.cross(r) / denominator;
+    if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+        Some((t, u))
+    } else {
+        None
+    }
+}
+
+// Smallest and largest corners of the box around the points, None if there aren't any
+pub fn bounding_box(points: impl IntoIterator<Item = Vec2d>) -> Option<(Vec2d, Vec2d)> {
+    points.into_iter().fold(None, |bounds, point| match bounds {
+        None => Some((point, point)),
+        Some((min, max)) => Some((
+            Vec2d::new(min.x.min(point.x), min.y.min(point.y)),
+            Vec2d::new(max.x.max(point.x), max.y.max(point.y)),
+        )),
+    })
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -15,6 +126,7 @@ pub enum Axis {
     Y,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum LineType {
     Horizontal(f32),
     Vertical(f32),
@@ -23,6 +135,7 @@ pub enum LineType {
     Diagonal(f32, f32),
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Boundary {
     pub line_type: LineType,
     pub positive_inf_within: bool,
@@ -43,6 +156,66 @@ impl Boundary {
         }
     }
 
+    // The line through `point` at right angles to `heading_radians`. The side the heading points
+    // away from is within
+    pub fn across(point: Vec2d, heading_radians: f32) -> Boundary {
+        let heading = Vec2d::from_heading(heading_radians);
+        let line_type = if heading.y.abs() < f32::EPSILON {
+            LineType::Vertical(point.x)
+        } else if heading.x.abs() < f32::EPSILON {
+            LineType::Horizontal(point.y)
+        } else {
+            let m = -heading.x / heading.y;
+            LineType::Diagonal(m, point.y - m * point.x)
+        };
+        let mut boundary = Boundary {
+            line_type,
+            positive_inf_within: true,
+        };
+        boundary.positive_inf_within = boundary.point_within(&(point - heading));
+        boundary
+    }
+
+    // Distance past the line in the direction of increasing x or y
+    fn offset(&self, point: &Vec2d) -> f32 {
+        match self.line_type {
+            LineType::Horizontal(y_intercept) => point.y - y_intercept,
+            LineType::Vertical(x_intercept) => point.x - x_intercept,
+            LineType::Diagonal(m, c_y) => point.y - (m * point.x + c_y),
+        }
+    }
+
+    pub fn distance(&self, point: &Vec2d) -> f32 {
+        match self.line_type {
+            LineType::Diagonal(m, _) => self.offset(point).abs() / (1.0 + m * m).sqrt(),
+            _ => self.offset(point).abs(),
+        }
+    }
+
+    // The point on the line closest to `point`
+    pub fn nearest_point(&self, point: &Vec2d) -> Vec2d {
+        match self.line_type {
+            LineType::Horizontal(y_intercept) => Vec2d::new(point.x, y_intercept),
+            LineType::Vertical(x_intercept) => Vec2d::new(x_intercept, point.y),
+            LineType::Diagonal(m, c_y) => {
+                let on_line = Vec2d::new(0.0, c_y);
+                let along = Vec2d::new(1.0, m).normalize();
+                on_line + along * (*point - on_line).dot(along)
+            }
+        }
+    }
+
+    // If moving from `from` to `to` takes a point from within the boundary to outside it, how far
+    // along the move it left
+    pub fn crossing(&self, from: &Vec2d, to: &Vec2d) -> Option<f32> {
+        if !self.point_within(from) || self.point_within(to) {
+            return None;
+        }
+        let (before, after) = (self.offset(from), self.offset(to));
+        let t = if before == after { 1.0 } else { before / (before - after) };
+        Some(t.clamp(0.0, 1.0))
+    }
+
     pub fn point_within(&self, point: &Vec2d) -> bool {
         // point > intercept    pos_inf_within  |   is_within
         //                                      |
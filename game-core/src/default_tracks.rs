@@ -1,6 +1,100 @@
-use crate::car::TerminationCondition;
-use crate::coordinates::Boundary;
-use crate::track::{ParallelRectSection, Track};
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use std::path::Path;
+
+use crate::car::{Car, PhysicsConstants, TerminationCondition};
+use crate::car_progress::CarProgress;
+use crate::centerline::{Centerline, ControlPoint};
+use crate::coordinates::Vec2d;
+use crate::gameloop::{run_until, TIME_PER_TICK};
+use crate::gate::SegmentGate;
+use crate::input::InputProvider;
+use crate::line_follower::LineFollower;
+use crate::track::{GridSlot, ParallelRectSection, Track, TrackMetadata};
+use crate::track_file::TrackFileError;
+
+// Reference times are only expected to move when the physics or the track changes
+pub const REFERENCE_TOLERANCE_S: f32 = 0.01;
+
+pub struct TrackPreset {
+    pub name: &'static str,
+    pub description: &'static str,
+    // Race time of a `LineFollower` with the default physics
+    pub reference_time_s: f32,
+    build: fn() -> Track,
+}
+
+impl TrackPreset {
+    pub fn build(&self) -> Track {
+        (self.build)()
+    }
+
+    // Drives a `LineFollower` over the whole race on its own
+    pub fn reference_run(&self, physics: &PhysicsConstants) -> CarProgress {
+        let track = self.build();
+        let mut car = Car::on_start_line(&track, self.name);
+        let mut input: Box<dyn InputProvider> = Box::new(LineFollower::new(*physics));
+        run_until(vec![(&mut car, &mut input)], &track, physics, TIME_PER_TICK)
+            .pop()
+            .unwrap_or_default()
+    }
+}
+
+pub const PRESETS: &[TrackPreset] = &[
+    TrackPreset {
+        name: "straight-sprint",
+        description: "The original 100 m wide straight",
+        reference_time_s: 5.914,
+        build: make_track,
+    },
+    TrackPreset {
+        name: "hairpin",
+        description: "Up, round a tight hairpin and back down",
+        reference_time_s: 17.302,
+        build: hairpin,
+    },
+    TrackPreset {
+        name: "chicane",
+        description: "A straight with a quick left-right flick in the middle",
+        reference_time_s: 12.992,
+        build: chicane,
+    },
+    TrackPreset {
+        name: "oval",
+        description: "Three laps of a wide oval",
+        reference_time_s: 41.258,
+        build: oval,
+    },
+    TrackPreset {
+        name: "figure-eight",
+        description: "Two loops joined by a crossroads the road passes through twice",
+        reference_time_s: 24.071,
+        build: figure_eight,
+    },
+    TrackPreset {
+        name: "slalom",
+        description: "A narrow road weaving left and right",
+        reference_time_s: 19.476,
+        build: slalom,
+    },
+    TrackPreset {
+        name: "narrow-maze",
+        description: "A corridor a few car widths wide with right-angle turns",
+        reference_time_s: 48.635,
+        build: narrow_maze,
+    },
+];
+
+pub fn preset(name: &str) -> Option<&'static TrackPreset> {
+    PRESETS.iter().find(|preset| preset.name == name)
+}
+
+// A track file, or the preset with that name if there's no such file
+pub fn load_track_or_preset(path: &Path) -> Result<Track, TrackFileError> {
+    match path.to_str().and_then(preset) {
+        Some(preset) if !path.exists() => Ok(preset.build()),
+        _ => Track::load(path),
+    }
+}
 
 pub fn make_track(// world: &mut World
 ) -> Track {
@@ -10,11 +104,212 @@ pub fn make_track(// world: &mut World
         top_y: 380.0,
         bottom_y: -10.0,
     };
-    Track {
-        start: Default::default(),
-        finish_line: Boundary::horizontal(350.0, true),
-        sections: vec![Box::new(track_sect)],
+    // There's little room behind the start, so the grid is a single row working out from the middle
+    let grid = (0..8)
+        .map(|i| {
+            let offset = ((i + 1) / 2) as f32 * 10.0;
+            GridSlot {
+                pos: Vec2d::new(if i % 2 == 1 { offset } else { -offset }, 0.0),
+                direction_radians: 0.0,
+            }
+        })
+        .collect();
+    Track::new(
+        TrackMetadata {
+            name: "Straight".to_string(),
+            author: None,
+        },
+        Default::default(),
+        0.0,
+        SegmentGate::across(Vec2d::new(0.0, 350.0), 100.0, 0.0).into(),
+        vec![Box::new(track_sect)],
+        TerminationCondition::Seconds(30.0),
+    )
+    .with_grid(grid)
+}
+
+fn centerline_track(name: &str, points: &[(f32, f32)], width: f32, closed: bool, seconds: f32) -> Track {
+    let points = points.iter().map(|(x, y)| ControlPoint::new(*x, *y, width)).collect();
+    Track::from_centerline(
+        Centerline::catmull_rom(points, closed),
+        TrackMetadata {
+            name: name.to_string(),
+            author: None,
+        },
+        TerminationCondition::Seconds(seconds),
+    )
+}
+
+fn hairpin() -> Track {
+    let points = [
+        (0.0, 0.0),
+        (0.0, 200.0),
+        (0.0, 260.0),
+        (30.0, 290.0),
+        (60.0, 260.0),
+        (60.0, 200.0),
+        (60.0, 0.0),
+    ];
+    centerline_track("Hairpin", &points, 20.0, false, 60.0)
+}
+
+fn chicane() -> Track {
+    let points = [
+        (0.0, 0.0),
+        (0.0, 150.0),
+        (0.0, 200.0),
+        (25.0, 230.0),
+        (25.0, 260.0),
+        (0.0, 290.0),
+        (0.0, 340.0),
+        (0.0, 450.0),
+    ];
+    centerline_track("Chicane", &points, 18.0, false, 60.0)
+}
+
+// Two straights joined by half circles, driven clockwise
+fn oval() -> Track {
+    let mut points = vec![(-100.0, 0.0), (-100.0, 100.0), (-100.0, 200.0)];
+    for (centre_y, from) in [(300.0, -FRAC_PI_2), (0.0, FRAC_PI_2)] {
+        points.extend((0..=4).map(|i| {
+            let point = Vec2d::new(0.0, centre_y) + Vec2d::from_heading(from + PI * i as f32 / 4.0) * 100.0;
+            (point.x, point.y)
+        }));
+        if centre_y > 0.0 {
+            points.extend([(100.0, 200.0), (100.0, 100.0)]);
+        }
+    }
+    points.pop();
+    centerline_track("Oval", &points, 30.0, true, 120.0).with_laps(3)
+}
+
+// A lemniscate standing on end, starting from the top of the upper loop
+fn figure_eight() -> Track {
+    let points = (0..16)
+        .map(|i| {
+            let t = FRAC_PI_2 + TAU * i as f32 / 16.0;
+            (100.0 * (2.0 * t).sin(), 200.0 * t.sin())
+        })
+        .collect::<Vec<_>>();
+    centerline_track("Figure eight", &points, 20.0, true, 90.0)
+}
+
+fn slalom() -> Track {
+    let mut points = vec![(0.0, 0.0), (0.0, 40.0)];
+    points.extend((1..=5).map(|i| (if i % 2 == 1 { 20.0 } else { -20.0 }, 40.0 + 60.0 * i as f32)));
+    points.extend([(0.0, 400.0), (0.0, 440.0)]);
+    centerline_track("Slalom", &points, 14.0, false, 60.0)
+}
+
+// A corridor 12 m wide zigzagging upwards. Gates across both mouths of every corner keep the
+// racing line inside it, each a metre back so the two at a corner don't touch
+fn narrow_maze() -> Track {
+    let corridor = |left_x: f32, right_x: f32, bottom_y: f32, top_y: f32| {
+        Box::new(ParallelRectSection {
+            left_x,
+            right_x,
+            top_y,
+            bottom_y,
+        }) as _
+    };
+    let sections = vec![
+        corridor(-6.0, 6.0, -40.0, 106.0),
+        corridor(-6.0, 106.0, 94.0, 106.0),
+        corridor(94.0, 106.0, 94.0, 206.0),
+        corridor(-6.0, 106.0, 194.0, 206.0),
+        corridor(-6.0, 6.0, 194.0, 305.0),
+    ];
+    let (up, right, left) = (0.0, FRAC_PI_2, -FRAC_PI_2);
+    let checkpoints = [
+        ((0.0, 93.0), up),
+        ((7.0, 100.0), right),
+        ((93.0, 100.0), right),
+        ((100.0, 107.0), up),
+        ((100.0, 193.0), up),
+        ((93.0, 200.0), left),
+        ((7.0, 200.0), left),
+        ((0.0, 207.0), up),
+    ]
+    .iter()
+    .map(|((x, y), direction)| SegmentGate::across(Vec2d::new(*x, *y), 12.0, *direction).into())
+    .collect();
+    Track::new(
+        TrackMetadata {
+            name: "Narrow maze".to_string(),
+            author: None,
+        },
+        Default::default(),
+        0.0,
+        SegmentGate::across(Vec2d::new(0.0, 290.0), 12.0, 0.0).into(),
+        sections,
+        TerminationCondition::Seconds(90.0),
+    )
+    .with_checkpoints(checkpoints)
+    // Single file, there's no room side by side
+    .with_grid(
+        (0..4)
+            .map(|i| GridSlot {
+                pos: Vec2d::new(0.0, -8.0 * i as f32),
+                direction_radians: 0.0,
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::CarState;
+
+    // A `LineFollower` still drives the preset in its reference time
+    fn keeps_its_reference_time(name: &str) {
+        let preset = PRESETS.iter().find(|preset| preset.name == name).unwrap();
+        assert!(preset.build().validate().diagnostics.is_empty(), "{} doesn't validate cleanly", name);
+        let progress = preset.reference_run(&PhysicsConstants::default());
+        assert_eq!(progress.state, CarState::Finished, "{} wasn't finished", name);
+        let time_s = progress.end_time.map(|end| end - progress.start_time);
+        assert!(
+            time_s.is_some_and(|time| (time - preset.reference_time_s).abs() <= REFERENCE_TOLERANCE_S),
+            "{} took {:?} instead of {}",
+            name,
+            time_s,
+            preset.reference_time_s
+        );
+    }
+
+    #[test]
+    fn straight_sprint() {
+        keeps_its_reference_time("straight-sprint");
+    }
+
+    #[test]
+    fn hairpin() {
+        keeps_its_reference_time("hairpin");
+    }
+
+    #[test]
+    fn chicane() {
+        keeps_its_reference_time("chicane");
+    }
+
+    #[test]
+    #[ignore = "three laps take a while in a debug build, run with --ignored or `tracks --check`"]
+    fn oval() {
+        keeps_its_reference_time("oval");
+    }
+
+    #[test]
+    fn figure_eight() {
+        keeps_its_reference_time("figure-eight");
+    }
+
+    #[test]
+    fn slalom() {
+        keeps_its_reference_time("slalom");
+    }
 
-        termination_condition: TerminationCondition::Seconds(30.0),
+    #[test]
+    fn narrow_maze() {
+        keeps_its_reference_time("narrow-maze");
     }
 }
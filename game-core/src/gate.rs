@@ -0,0 +1,205 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::coordinates::{segment_intersection, Boundary, LineType, Vec2d};
+
+// A finite line across the track that only counts when driven through the right way
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SegmentGate {
+    pub start: Vec2d,
+    pub end: Vec2d,
+    // Heading a car has to be travelling in to pass through, using the car's convention. Only
+    // the part across the gate matters
+    pub direction_radians: f32,
+}
+
+impl SegmentGate {
+    // A gate `width` wide centred on `centre`, at right angles to `direction_radians`
+    pub fn across(centre: Vec2d, width: f32, direction_radians: f32) -> SegmentGate {
+        let half = Vec2d::from_heading(direction_radians).perp_right() * (width / 2.0);
+        SegmentGate {
+            start: centre - half,
+            end: centre + half,
+            direction_radians,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        let along = self.end - self.start;
+        if along.length() <= 0.0 || !along.length().is_finite() {
+            return Err("the gate's ends are in the same place".to_string());
+        }
+        if along.normalize().cross(Vec2d::from_heading(self.direction_radians)).abs() < f32::EPSILON {
+            return Err("the gate's direction runs along it rather than through it".to_string());
+        }
+        Ok(())
+    }
+
+    // Distance from the nearest point on the gate
+    pub fn distance(&self, point: &Vec2d) -> f32 {
+        let along = self.end - self.start;
+        let len_sq = along.dot(along);
+        let t = if len_sq > 0.0 {
+            ((*point - self.start).dot(along) / len_sq).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        point.distance(self.start.lerp(self.end, t))
+    }
+
+    // How far from `from` to `to` the gate was passed through in its direction. A move that
+    // starts on the gate doesn't count, so one that ends exactly on it isn't counted twice
+    pub fn crossing(&self, from: &Vec2d, to: &Vec2d) -> Option<f32> {
+        let (t, _) = segment_intersection(*from, *to, self.start, self.end)?;
+        let forward = Vec2d::from_heading(self.direction_radians);
+        let normal = (self.end - self.start).perp_right();
+        let normal = if normal.dot(forward) < 0.0 { -normal } else { normal };
+        (t > 0.0 && (*to - *from).dot(normal) > 0.0).then_some(t)
+    }
+}
+
+// Finish lines and checkpoints. Older track files have half-plane boundaries, which are passed by
+// going from within them to outside wherever that happens
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, try_from = "GateFields")]
+pub enum Gate {
+    Segment(SegmentGate),
+    Line(Boundary),
+}
+
+impl Gate {
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Gate::Segment(segment) => segment.check(),
+            Gate::Line(_) => Ok(()),
+        }
+    }
+
+    pub fn distance(&self, point: &Vec2d) -> f32 {
+        match self {
+            Gate::Segment(segment) => segment.distance(point),
+            Gate::Line(boundary) => boundary.distance(point),
+        }
+    }
+
+    pub fn crossing(&self, from: &Vec2d, to: &Vec2d) -> Option<f32> {
+        match self {
+            Gate::Segment(segment) => segment.crossing(from, to),
+            Gate::Line(boundary) => boundary.crossing(from, to),
+        }
+    }
+
+    // Where a car coming from `from` is aimed at. Half-plane boundaries go on forever so the
+    // nearest point on them is used instead of the middle
+    pub fn centre_near(&self, from: &Vec2d) -> Vec2d {
+        match self {
+            Gate::Segment(segment) => segment.start.lerp(segment.end, 0.5),
+            Gate::Line(boundary) => boundary.nearest_point(from),
+        }
+    }
+}
+
+// Either kind of gate is written as a plain struct, which is told apart by its fields. RON can't
+// read an untagged enum holding another enum, so this is read first
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GateFields {
+    #[serde(default, deserialize_with = "present")]
+    start: Option<Vec2d>,
+    #[serde(default, deserialize_with = "present")]
+    end: Option<Vec2d>,
+    #[serde(default, deserialize_with = "present")]
+    direction_radians: Option<f32>,
+    #[serde(default, deserialize_with = "present")]
+    line_type: Option<LineType>,
+    #[serde(default, deserialize_with = "present")]
+    positive_inf_within: Option<bool>,
+}
+
+// Fields are written bare rather than as RON's `Some(..)`, so missing is the only way to be None
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+impl TryFrom<GateFields> for Gate {
+    type Error = String;
+
+    fn try_from(fields: GateFields) -> Result<Gate, String> {
+        match fields {
+            GateFields {
+                start: Some(start),
+                end: Some(end),
+                direction_radians: Some(direction_radians),
+                line_type: None,
+                positive_inf_within: None,
+            } => Ok(Gate::Segment(SegmentGate {
+                start,
+                end,
+                direction_radians,
+            })),
+            GateFields {
+                start: None,
+                end: None,
+                direction_radians: None,
+                line_type: Some(line_type),
+                positive_inf_within: Some(positive_inf_within),
+            } => Ok(Gate::Line(Boundary {
+                line_type,
+                positive_inf_within,
+            })),
+            _ => Err("a gate needs either start, end and direction_radians or line_type and positive_inf_within".to_string()),
+        }
+    }
+}
+
+impl From<Boundary> for Gate {
+    fn from(boundary: Boundary) -> Gate {
+        Gate::Line(boundary)
+    }
+}
+
+impl From<SegmentGate> for Gate {
+    fn from(segment: SegmentGate) -> Gate {
+        Gate::Segment(segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 20 m wide across the y axis at y = 10, passed going up
+    fn gate() -> SegmentGate {
+        SegmentGate::across(Vec2d::new(0.0, 10.0), 20.0, 0.0)
+    }
+
+    #[test]
+    fn crossings_report_how_far_along_the_move_they_were() {
+        let t = gate().crossing(&Vec2d::new(0.0, 8.0), &Vec2d::new(0.0, 16.0)).unwrap();
+        assert!((t - 0.25).abs() < 1e-6, "crossed at {}", t);
+    }
+
+    #[test]
+    fn only_crossings_in_the_gates_direction_count() {
+        let gate = gate();
+        assert_eq!(gate.crossing(&Vec2d::new(0.0, 12.0), &Vec2d::new(0.0, 8.0)), None);
+        // Diagonally is still through it the right way
+        assert!(gate.crossing(&Vec2d::new(-5.0, 8.0), &Vec2d::new(5.0, 12.0)).is_some());
+    }
+
+    #[test]
+    fn moves_past_the_ends_or_starting_on_the_gate_miss_it() {
+        let gate = gate();
+        assert_eq!(gate.crossing(&Vec2d::new(11.0, 8.0), &Vec2d::new(11.0, 12.0)), None);
+        assert_eq!(gate.crossing(&Vec2d::new(0.0, 10.0), &Vec2d::new(0.0, 12.0)), None);
+        assert!(gate.crossing(&Vec2d::new(0.0, 8.0), &Vec2d::new(0.0, 10.0)).is_some());
+    }
+
+    #[test]
+    fn old_boundaries_and_segments_both_load() {
+        let boundary: Gate =
+            serde_json::from_str(r#"{"line_type": {"Horizontal": 350.0}, "positive_inf_within": false}"#).unwrap();
+        assert!(matches!(boundary, Gate::Line(_)));
+        let segment: Gate = serde_json::from_str(&serde_json::to_string(&Gate::Segment(gate())).unwrap()).unwrap();
+        assert_eq!(segment, Gate::Segment(gate()));
+    }
+}
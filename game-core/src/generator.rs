@@ -0,0 +1,255 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+use std::fmt;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::car::TerminationCondition;
+use crate::centerline::{Centerline, CenterlineSection, ControlPoint};
+use crate::coordinates::Vec2d;
+use crate::track::{Track, TrackMetadata};
+
+// Shapes are drawn until one passes every check, giving up after this many
+pub const MAX_ATTEMPTS: u32 = 50;
+// Gap kept between pieces of road that pass close to each other, so neither can be reached from
+// the other
+pub const MIN_CLEARANCE_M: f32 = 5.0;
+// Cars get long enough to cover the whole race at this average speed
+pub const MIN_AVERAGE_SPEED_MS: f32 = 8.0;
+pub const MIN_TIME_LIMIT_S: f32 = 30.0;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    // Length of a lap, or of the whole road when it isn't a loop
+    pub length_m: f32,
+    pub min_width_m: f32,
+    pub max_width_m: f32,
+    // From 0 for long sweeping bends to 1 for hairpins one after another
+    pub difficulty: f32,
+    pub closed: bool,
+    pub laps: u32,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            seed: 0,
+            length_m: 1500.0,
+            min_width_m: 15.0,
+            max_width_m: 25.0,
+            difficulty: 0.5,
+            closed: true,
+            laps: 1,
+        }
+    }
+}
+
+impl GeneratorConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if !(self.min_width_m > 0.0 && self.min_width_m <= self.max_width_m && self.max_width_m.is_finite()) {
+            return Err(format!(
+                "widths {} to {} aren't a positive range",
+                self.min_width_m, self.max_width_m
+            ));
+        }
+        if !(self.length_m.is_finite() && self.length_m >= 20.0 * self.max_width_m) {
+            return Err(format!(
+                "a length of {} m is too short for road {} m wide",
+                self.length_m, self.max_width_m
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.difficulty) {
+            return Err(format!("difficulty {} isn't between 0 and 1", self.difficulty));
+        }
+        if self.laps == 0 || (!self.closed && self.laps != 1) {
+            return Err(format!("{} laps can't be raced on this road", self.laps));
+        }
+        Ok(())
+    }
+
+    // Space between control points, closer together for a harder track so there's room for
+    // more bends
+    fn spacing_m(&self) -> f32 {
+        250.0 + (110.0 - 250.0) * self.difficulty
+    }
+}
+
+#[derive(Debug)]
+pub enum GenerateError {
+    InvalidConfig(String),
+    NoValidTrack { attempts: u32 },
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerateError::InvalidConfig(message) => write!(f, "invalid generator settings: {}", message),
+            GenerateError::NoValidTrack { attempts } => {
+                write!(f, "no valid track was found in {} attempts, try another seed", attempts)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+impl Track {
+    // The same config always gives the same track
+    pub fn generate(config: &GeneratorConfig) -> Result<Track, GenerateError> {
+        config.check().map_err(GenerateError::InvalidConfig)?;
+        let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+        for _ in 0..MAX_ATTEMPTS {
+            let centerline = if config.closed {
+                loop_centerline(config, &mut rng)
+            } else {
+                road_centerline(config, &mut rng)
+            };
+            let section = CenterlineSection::new(centerline);
+            if folds(&section) || overlaps(&section) {
+                continue;
+            }
+            let laps = if config.closed { config.laps } else { 1 };
+            let seconds = (config.length_m * laps as f32 / MIN_AVERAGE_SPEED_MS).max(MIN_TIME_LIMIT_S);
+            let track = Track::from_centerline(
+                section.centerline,
+                TrackMetadata {
+                    name: format!("Generated {}", config.seed),
+                    author: None,
+                },
+                TerminationCondition::Seconds(seconds.ceil()),
+            )
+            .with_laps(laps);
+            if track.validate().diagnostics.is_empty() {
+                return Ok(track);
+            }
+        }
+        Err(GenerateError::NoValidTrack { attempts: MAX_ATTEMPTS })
+    }
+}
+
+// Points round a circle, each pushed in or out and along by a random amount, harder tracks by more
+fn loop_centerline(config: &GeneratorConfig, rng: &mut ChaCha8Rng) -> Centerline {
+    let count = ((config.length_m / config.spacing_m()).round() as usize).max(8);
+    let wobble = 0.1 + 0.25 * config.difficulty;
+    let stretch = rng.gen_range(0.7..1.3);
+    let points = (0..count)
+        .map(|i| {
+            let angle = (i as f32 + rng.gen_range(-0.3..0.3) * config.difficulty) / count as f32 * TAU;
+            let radius = 1.0 + rng.gen_range(-wobble..wobble);
+            let pos = Vec2d::from_heading(angle) * radius;
+            ControlPoint {
+                pos: Vec2d::new(pos.x * stretch, pos.y / stretch),
+                width: rng.gen_range(config.min_width_m..=config.max_width_m),
+            }
+        })
+        .collect();
+    scaled_to_length(Centerline::catmull_rom(points, true), config.length_m)
+}
+
+// A random walk, turning a little or a lot between points depending on the difficulty
+fn road_centerline(config: &GeneratorConfig, rng: &mut ChaCha8Rng) -> Centerline {
+    let count = ((config.length_m / config.spacing_m()).round() as usize).max(3) + 1;
+    let max_turn = 0.3 + 1.1 * config.difficulty;
+    let mut heading = rng.gen_range(0.0..TAU);
+    let mut pos = Vec2d::default();
+    let points = (0..count)
+        .map(|_| {
+            let point = ControlPoint {
+                pos,
+                width: rng.gen_range(config.min_width_m..=config.max_width_m),
+            };
+            heading += rng.gen_range(-max_turn..max_turn);
+            pos = pos + Vec2d::from_heading(heading);
+            point
+        })
+        .collect();
+    scaled_to_length(Centerline::catmull_rom(points, false), config.length_m)
+}
+
+// Scaling the control points scales the length of the curve through them by the same amount
+fn scaled_to_length(mut centerline: Centerline, length_m: f32) -> Centerline {
+    let length = CenterlineSection::new(centerline.clone()).length();
+    let scale = length_m / length.max(f32::EPSILON);
+    for point in &mut centerline.points {
+        point.pos = point.pos * scale;
+    }
+    centerline
+}
+
+// A bend tighter than half the road is wide turns the inside kerb back on itself
+fn folds(section: &CenterlineSection) -> bool {
+    let samples = section.samples();
+    let n = samples.len();
+    let corners = if section.centerline.closed { n } else { n.saturating_sub(2) };
+    (0..corners).any(|i| {
+        let window = [&samples[i], &samples[(i + 1) % n], &samples[(i + 2) % n]];
+        let (before, after) = (window[1].pos - window[0].pos, window[2].pos - window[1].pos);
+        let turn = before.cross(after).atan2(before.dot(after)).abs();
+        let radius = (before.length() + after.length()) / 2.0 / turn.max(f32::EPSILON);
+        radius < (window[1].width + MIN_CLEARANCE_M) / 2.0
+    })
+}
+
+// Two pieces of road far apart along the centerline but close together on the ground
+fn overlaps(section: &CenterlineSection) -> bool {
+    let samples = section.samples();
+    let length = section.length();
+    // No two samples further apart than a cell can be too close, so each is only compared with
+    // those in its own and the neighbouring cells
+    let cell_m = samples.iter().map(|sample| sample.width).fold(0.0, f32::max) + MIN_CLEARANCE_M;
+    let cell_of = |pos: Vec2d| ((pos.x / cell_m).floor() as i64, (pos.y / cell_m).floor() as i64);
+    let mut by_cell = samples.iter().enumerate().map(|(i, sample)| (cell_of(sample.pos), i)).collect::<Vec<_>>();
+    by_cell.sort_unstable();
+    samples.iter().enumerate().any(|(i, a)| {
+        let (column, row) = cell_of(a.pos);
+        (column - 1..=column + 1)
+            .flat_map(|column| (row - 1..=row + 1).map(move |row| (column, row)))
+            .flat_map(|cell| {
+                let start = by_cell.partition_point(|(other, _)| *other < cell);
+                by_cell[start..].iter().take_while(move |(other, _)| *other == cell)
+            })
+            .filter(|(_, j)| *j > i)
+            .any(|(_, j)| {
+                let b = &samples[*j];
+                let along = (b.distance - a.distance).abs();
+                let along = if section.centerline.closed { along.min(length - along) } else { along };
+                let apart = (a.width + b.width) / 2.0 + MIN_CLEARANCE_M;
+                // Neighbours round a bend that's passed `folds` are always this close
+                along > apart * FRAC_PI_2 && a.pos.distance(b.pos) < apart
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::track::GRID_SLOTS;
+    use crate::track_file::TrackFile;
+
+    fn saved(track: &Track) -> String {
+        serde_json::to_string(&TrackFile::from_track(track).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_track() {
+        for closed in [true, false] {
+            let config = GeneratorConfig {
+                seed: 7,
+                length_m: 600.0,
+                closed,
+                ..Default::default()
+            };
+            let track = Track::generate(&config).unwrap();
+            assert_eq!(saved(&track), saved(&Track::generate(&config).unwrap()));
+            let other = Track::generate(&GeneratorConfig { seed: 8, ..config }).unwrap();
+            assert_ne!(saved(&track), saved(&other));
+
+            // Every car gets a place on the road
+            assert_eq!(track.grid.len(), GRID_SLOTS);
+            assert!(track.grid.iter().all(|slot| track.has_room_for(slot)));
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::car::{Car, CarState};
+use crate::coordinates::Vec2d;
+use crate::track::Track;
 
 #[derive(Debug, Copy, Clone)]
 pub enum Accelerator {
@@ -62,8 +66,90 @@ impl KeyInput {
     }
 }
 
+// Proportional controls, as from a gamepad or a controller
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnalogInput {
+    // 0 to 1
+    pub throttle: f32,
+    // 0 to 1
+    pub brake: f32,
+    // -1 is full left lock, 1 is full right
+    pub steering: f32,
+}
+
+impl AnalogInput {
+    // Values out of range are clamped
+    pub fn new(throttle: f32, brake: f32, steering: f32) -> AnalogInput {
+        AnalogInput {
+            throttle: throttle.clamp(0.0, 1.0),
+            brake: brake.clamp(0.0, 1.0),
+            steering: steering.clamp(-1.0, 1.0),
+        }
+    }
+}
+
+impl From<KeyInput> for AnalogInput {
+    fn from(value: KeyInput) -> Self {
+        let (throttle, brake) = match value.acceleration {
+            None => (0.0, 0.0),
+            Some(Accelerator::Accelerate) => (1.0, 0.0),
+            Some(Accelerator::Brake) => (0.0, 1.0),
+        };
+        let steering = match value.direction {
+            None => 0.0,
+            Some(Direction::Left) => -1.0,
+            Some(Direction::Right) => 1.0,
+        };
+        AnalogInput {
+            throttle,
+            brake,
+            steering,
+        }
+    }
+}
+
+// Everything a controller gets to see when choosing the input for the next tick
+#[derive(Copy, Clone)]
+pub struct Observation<'a> {
+    pub pos: Vec2d,
+    pub direction_radians: f32,
+    pub velocity: f32,
+    pub lateral_velocity: f32,
+    pub state: CarState,
+    pub tick: u64,
+    pub time_s: f32,
+    pub track: &'a Track,
+}
+
+impl<'a> Observation<'a> {
+    pub fn new(car: &Car, track: &'a Track, tick: u64, time_s: f32) -> Observation<'a> {
+        Observation {
+            pos: car.pos,
+            direction_radians: car.direction_radians,
+            velocity: car.velocity,
+            lateral_velocity: car.lateral_velocity,
+            state: car.state,
+            tick,
+            time_s,
+            track,
+        }
+    }
+
+    pub fn velocity_vector(&self) -> Vec2d {
+        let forward = Vec2d::from_heading(self.direction_radians);
+        forward * self.velocity + forward.perp_right() * self.lateral_velocity
+    }
+}
+
 pub trait InputProvider {
-    fn get_input(&self) -> KeyInput;
+    fn get_input(&mut self, observation: &Observation) -> AnalogInput;
+}
+
+// Lets a closure act as a controller
+impl<F: FnMut(&Observation) -> AnalogInput> InputProvider for F {
+    fn get_input(&mut self, observation: &Observation) -> AnalogInput {
+        self(observation)
+    }
 }
 
 pub struct SingleInput {
@@ -77,8 +163,8 @@ impl From<KeyInput> for SingleInput {
 }
 
 impl InputProvider for SingleInput {
-    fn get_input(&self) -> KeyInput {
-        self.input
+    fn get_input(&mut self, _observation: &Observation) -> AnalogInput {
+        self.input.into()
     }
 }
 
@@ -1,7 +1,21 @@
 pub mod car;
+pub mod centerline;
+pub mod collision;
 pub mod car_progress;
 pub mod input;
 pub mod track;
+pub mod track_file;
+pub mod validate;
 pub mod coordinates;
+pub mod gate;
+pub mod generator;
+pub mod racing_line;
+pub mod line_follower;
+mod spatial_index;
+pub mod sensors;
 pub mod gameloop;
+pub mod batch;
+pub mod script;
+pub mod replay;
+pub mod race;
 pub mod default_tracks;
\ No newline at end of file
@@ -0,0 +1,109 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::car::PhysicsConstants;
+use crate::input::{AnalogInput, InputProvider, Observation};
+use crate::racing_line::RacingLine;
+
+// How far either side of a point on the line is looked at to judge how sharply it bends
+const CURVATURE_SPAN_M: f32 = 5.0;
+// Steps taken along the line when finding the car on it or looking for bends ahead
+const SEARCH_STEP_M: f32 = 1.0;
+// Share of the car's limits it drives to, leaving room for the line cutting corners
+const MARGIN: f32 = 0.85;
+
+// A plain deterministic driver that steers for a point ahead on the racing line and brakes in
+// time for the bends it can see coming. It's no racer, but it gets round every built-in track and
+// its times show when a physics change makes cars faster or slower
+#[derive(Clone, Debug)]
+pub struct LineFollower {
+    pub physics: PhysicsConstants,
+    // Distance along the line the car has reached, carried on past the finish on a loop
+    pub distance: f32,
+}
+
+impl LineFollower {
+    pub fn new(physics: PhysicsConstants) -> LineFollower {
+        LineFollower { physics, distance: 0.0 }
+    }
+
+    // Fastest the car can take a bend of `radius_m` under its physics model
+    fn corner_speed(&self, radius_m: f32) -> f32 {
+        match self.physics {
+            PhysicsConstants::Arcade(consts) => radius_m * consts.turn_rate_rs * MARGIN,
+            PhysicsConstants::Bicycle(consts) => {
+                let grip = (consts.front_grip_mss * radius_m).sqrt();
+                // Lock is taken away with speed, so the car has to be slow enough to still have enough
+                let lock = |speed: f32| {
+                    let fraction = speed / consts.max_forward_speed_ms;
+                    consts.max_steering_radians * (1.0 + (consts.high_speed_steering_fraction - 1.0) * fraction)
+                };
+                let mut speed = grip.min(consts.max_forward_speed_ms);
+                while speed > 1.0 && consts.wheelbase_m / lock(speed).tan() > radius_m {
+                    speed -= 1.0;
+                }
+                speed * MARGIN
+            }
+        }
+    }
+
+    fn braking_mss(&self) -> f32 {
+        match self.physics {
+            PhysicsConstants::Arcade(consts) => consts.braking_acceleration_mss * MARGIN,
+            PhysicsConstants::Bicycle(consts) => consts.braking_acceleration_mss * MARGIN,
+        }
+    }
+
+    fn radius_at(line: &RacingLine, distance: f32, wrap: bool) -> f32 {
+        let point = |d: f32| line.point_at(if wrap { d.rem_euclid(line.lap_length()) } else { d });
+        let (before, here, after) = (
+            point(distance - CURVATURE_SPAN_M),
+            point(distance),
+            point(distance + CURVATURE_SPAN_M),
+        );
+        let (a, b) = (here - before, after - here);
+        let turn = a.cross(b).atan2(a.dot(b)).abs();
+        // Straight, or off the end of the line
+        if turn < f32::EPSILON || a.length() * b.length() == 0.0 {
+            return f32::INFINITY;
+        }
+        (a.length() + b.length()) / 2.0 / turn
+    }
+}
+
+impl InputProvider for LineFollower {
+    fn get_input(&mut self, observation: &Observation) -> AnalogInput {
+        let track = observation.track;
+        let line = track.racing_line();
+        let wrap = track.laps() > 1 && line.lap_length() > 0.0;
+        let point = |d: f32| line.point_at(if wrap { d.rem_euclid(line.lap_length()) } else { d });
+
+        // Only a little way back and a little further on, so a road crossing itself doesn't
+        // pull the car onto the other branch
+        let speed = observation.velocity.max(0.0);
+        let from = if wrap { self.distance - 5.0 } else { (self.distance - 5.0).max(0.0) };
+        let gap = |d: f32| point(d).distance(observation.pos);
+        self.distance = (0..=((10.0 + speed) / SEARCH_STEP_M) as usize)
+            .map(|i| from + i as f32 * SEARCH_STEP_M)
+            .fold(from, |best, d| if gap(d) <= gap(best) { d } else { best });
+
+        let target = point(self.distance + 6.0 + 0.4 * speed);
+        let error = ((target - observation.pos).heading() - observation.direction_radians + PI).rem_euclid(TAU) - PI;
+        let steering = error * 3.0;
+
+        // The slowest it could need to be anywhere it could still brake for
+        let braking = self.braking_mss();
+        let horizon = speed * speed / (2.0 * braking) + 20.0;
+        let target_speed = (0..=(horizon / SEARCH_STEP_M) as usize)
+            .map(|i| {
+                let ahead = i as f32 * SEARCH_STEP_M;
+                let corner = self.corner_speed(LineFollower::radius_at(line, self.distance + ahead, wrap));
+                (corner * corner + 2.0 * braking * ahead).sqrt()
+            })
+            .fold(f32::MAX, f32::min);
+        if speed < target_speed {
+            AnalogInput::new(1.0, 0.0, steering)
+        } else {
+            AnalogInput::new(0.0, 1.0, steering)
+        }
+    }
+}
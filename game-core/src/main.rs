@@ -1,23 +1,515 @@
-use rust_driving_game_core::car::Car;
-use rust_driving_game_core::default_tracks::make_track;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use rust_driving_game_core::batch::{BatchRun, BatchRunner};
+use rust_driving_game_core::car::{BicycleConstants, Car, CarState, PhysicsConstants};
+use rust_driving_game_core::car_progress::CarProgress;
+use rust_driving_game_core::collision::CarCollisionResponse;
+use rust_driving_game_core::default_tracks::{load_track_or_preset, make_track, PRESETS, REFERENCE_TOLERANCE_S};
 use rust_driving_game_core::gameloop::{run_until, TIME_PER_TICK};
+use rust_driving_game_core::generator::GeneratorConfig;
 use rust_driving_game_core::input::{InputProvider, KeyInput, SingleInput};
+use rust_driving_game_core::race::RaceSession;
+use rust_driving_game_core::replay::Replay;
+use rust_driving_game_core::script::ScriptedInput;
+use rust_driving_game_core::track::Track;
+use rust_driving_game_core::validate::{Diagnostic, Validation};
 
-fn main() {
-    println!("Hello, world!");
+// Without a subcommand this runs `simulate` with the default track and demo cars
+#[derive(Parser)]
+#[command(about = "Runs the driving game without graphics")]
+struct Cli {
+    #[arg(long, global = true, help = "Print results as JSON")]
+    json: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Race one car per input script, or four fixed-input cars without any")]
+    Simulate(SimulateArgs),
+    #[command(about = "Race cars from a grid and print the classification")]
+    Race(RaceArgs),
+    #[command(about = "Check that a track file or built-in track loads and can be raced on")]
+    ValidateTrack { path: PathBuf },
+    #[command(about = "Rerun a recorded replay and check every car ends exactly as it did")]
+    Replay {
+        path: PathBuf,
+        #[arg(long, help = "Track file or preset the replay was recorded on, the straight sprint if not given")]
+        track: Option<PathBuf>,
+    },
+    #[command(about = "Measure how many car ticks a second the engine runs")]
+    Bench(BenchArgs),
+    #[command(about = "Generate a random track from a seed and save it")]
+    GenerateTrack(GenerateArgs),
+    #[command(about = "List the built-in tracks that can be given by name instead of a file")]
+    Tracks {
+        #[arg(long, help = "Drive every track and compare the times against the reference times")]
+        check: bool,
+    },
+}
+
+const TRACK_HELP: &str = "Track file or built-in track name, the straight sprint if not given";
+
+#[derive(Args, Default)]
+struct SimulateArgs {
+    #[arg(long, help = TRACK_HELP)]
+    track: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t)]
+    physics: PhysicsModel,
+    #[arg(long = "script", help = "Input script for a car, may be repeated")]
+    scripts: Vec<PathBuf>,
+    #[arg(long, help = "Save a replay of the race")]
+    record: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct RaceArgs {
+    #[arg(long, help = TRACK_HELP)]
+    track: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t)]
+    physics: PhysicsModel,
+    #[arg(long = "script", help = "Input script for a car, may be repeated, in grid order")]
+    scripts: Vec<PathBuf>,
+    #[arg(long, value_enum, help = "What happens when cars touch, they pass through unnoticed if not given")]
+    car_collisions: Option<CarCollisionMode>,
+    #[arg(long, default_value_t = 0.5, help = "Share of their closing speed bouncing cars keep")]
+    restitution: f32,
+}
 
-    let track = make_track();
+#[derive(Args)]
+struct BenchArgs {
+    #[arg(long, help = TRACK_HELP)]
+    track: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t)]
+    physics: PhysicsModel,
+    #[arg(long, default_value_t = 4)]
+    cars: usize,
+    #[arg(long, default_value_t = 10)]
+    runs: usize,
+    #[arg(long, help = "Threads to spread the cars over, one per CPU if not given")]
+    threads: Option<usize>,
+}
+
+#[derive(Args)]
+struct GenerateArgs {
+    #[arg(help = "Where to save the track, the format is picked from the extension")]
+    path: PathBuf,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    #[arg(long, default_value_t = 1500.0, help = "Length of a lap in metres, or of the whole road with --open")]
+    length: f32,
+    #[arg(long, default_value_t = 15.0)]
+    min_width: f32,
+    #[arg(long, default_value_t = 25.0)]
+    max_width: f32,
+    #[arg(long, default_value_t = 0.5, help = "From 0 for gentle bends to 1 for hairpins")]
+    difficulty: f32,
+    #[arg(long, help = "A point-to-point road rather than a loop")]
+    open: bool,
+    #[arg(long, default_value_t = 1)]
+    laps: u32,
+}
 
-    let mut inputs: Vec<Box<dyn InputProvider>> = vec![
-        KeyInput::from_directions(true, false, false, false),
-        KeyInput::from_directions(false, true, false, false),
-        KeyInput::from_directions(false, false, true, false),
-        KeyInput::from_directions(false, false, false, true),
-    ].into_iter().map(|k| Box::new(SingleInput::from(k)) as Box<dyn InputProvider>).collect();
-    let mut cars = ["Up", "Down", "Left", "Right"].map(|label| Car::new(track.start, label)).to_vec();
+#[derive(Copy, Clone, Default, ValueEnum)]
+enum PhysicsModel {
+    #[default]
+    Arcade,
+    Bicycle,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum CarCollisionMode {
+    Ghost,
+    Bounce,
+    CrashBoth,
+}
+
+impl CarCollisionMode {
+    fn response(self, restitution: f32) -> CarCollisionResponse {
+        match self {
+            CarCollisionMode::Ghost => CarCollisionResponse::Ghost,
+            CarCollisionMode::Bounce => CarCollisionResponse::Bounce { restitution },
+            CarCollisionMode::CrashBoth => CarCollisionResponse::CrashBoth,
+        }
+    }
+}
+
+impl PhysicsModel {
+    fn constants(self) -> PhysicsConstants {
+        match self {
+            PhysicsModel::Arcade => PhysicsConstants::default(),
+            PhysicsModel::Bicycle => PhysicsConstants::Bicycle(BicycleConstants::default()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CarResult {
+    label: String,
+    state: CarState,
+    // Time from leaving the start line to the end of the run
+    time_s: Option<f32>,
+    progress: CarProgress,
+}
+
+impl CarResult {
+    fn new(label: &str, progress: &CarProgress) -> CarResult {
+        CarResult {
+            label: label.to_string(),
+            state: progress.state,
+            time_s: progress.end_time.map(|end| end - progress.start_time),
+            progress: progress.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TrackReport {
+    path: PathBuf,
+    valid: bool,
+    error: Option<String>,
+    name: Option<String>,
+    sections: Option<usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Serialize)]
+struct ReplayReport {
+    path: PathBuf,
+    verified: bool,
+    error: Option<String>,
+    cars: Vec<CarResult>,
+}
+
+#[derive(Serialize)]
+struct BenchReport {
+    runs: usize,
+    cars: usize,
+    threads: usize,
+    car_ticks: u64,
+    elapsed_s: f64,
+    car_ticks_per_second: f64,
+}
+
+#[derive(Serialize)]
+struct PresetReport {
+    name: &'static str,
+    description: &'static str,
+    reference_time_s: f32,
+    // Only filled in when the tracks are checked
+    state: Option<CarState>,
+    time_s: Option<f32>,
+    matches: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct GenerateReport {
+    path: PathBuf,
+    name: String,
+    // Along the racing line, for one lap
+    length_m: f32,
+    config: GeneratorConfig,
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    exit(1);
+}
+
+fn print_json(value: &impl Serialize) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => fail(format!("Failed to write JSON: {}", e)),
+    }
+}
+
+fn load_track(path: Option<&Path>) -> Track {
+    match path {
+        Some(path) => {
+            load_track_or_preset(path).unwrap_or_else(|e| fail(format!("Failed to load track {}: {}", path.display(), e)))
+        }
+        None => make_track(),
+    }
+}
+
+const FIXED_INPUTS: [(&str, [bool; 4]); 4] = [
+    ("Up", [true, false, false, false]),
+    ("Down", [false, true, false, false]),
+    ("Left", [false, false, true, false]),
+    ("Right", [false, false, false, true]),
+];
+
+// The demo cars, each holding one arrow key, repeated to make up `count` cars
+fn fixed_keys(count: usize) -> impl Iterator<Item = (&'static str, KeyInput)> {
+    FIXED_INPUTS
+        .iter()
+        .cycle()
+        .take(count)
+        .map(|(label, [up, down, left, right])| (*label, KeyInput::from_directions(*up, *down, *left, *right)))
+}
+
+fn fixed_inputs(count: usize) -> (Vec<String>, Vec<Box<dyn InputProvider>>) {
+    fixed_keys(count)
+        .map(|(label, key)| (label.to_string(), Box::new(SingleInput::from(key)) as Box<dyn InputProvider>))
+        .unzip()
+}
+
+fn print_results(results: &[CarResult]) {
+    for result in results {
+        match result.time_s {
+            Some(time_s) => println!("Car: {}. {} in {}", result.label, result.state, time_s),
+            None => println!("Car: {}. {}", result.label, result.state),
+        }
+    }
+}
+
+// One car per script, labelled by the script's file name, or the demo cars without any
+fn load_entrants(scripts: &[PathBuf]) -> (Vec<String>, Vec<Box<dyn InputProvider>>) {
+    if scripts.is_empty() {
+        return fixed_inputs(FIXED_INPUTS.len());
+    }
+    scripts
+        .iter()
+        .map(|path| {
+            let script = ScriptedInput::load(path)
+                .unwrap_or_else(|e| fail(format!("Failed to load input script {}: {}", path.display(), e)));
+            let label = path.file_stem().map_or(path.display().to_string(), |s| s.to_string_lossy().into_owned());
+            (label, Box::new(script) as Box<dyn InputProvider>)
+        })
+        .unzip()
+}
+
+fn simulate(args: SimulateArgs, json: bool) {
+    let track = load_track(args.track.as_deref());
+    let physics = args.physics.constants();
+    let (labels, mut inputs) = load_entrants(&args.scripts);
+    let mut cars = labels.iter().map(|label| Car::on_start_line(&track, label)).collect::<Vec<_>>();
     let car_input = cars.iter_mut().zip(inputs.iter_mut()).collect::<Vec<_>>();
-    let progress  = run_until(car_input, &track, TIME_PER_TICK);
-    for (i, car) in cars.iter().enumerate() {
-        println!("Car: {}. {} in {}", car.label, car.state.to_string(), progress[i].end_time.unwrap() - progress[i].start_time)
+    let progress = match &args.record {
+        Some(path) => {
+            let (progress, replay) = Replay::record(car_input, &track, &physics, TIME_PER_TICK);
+            replay
+                .save(path)
+                .unwrap_or_else(|e| fail(format!("Failed to save replay {}: {}", path.display(), e)));
+            progress
+        }
+        None => run_until(car_input, &track, &physics, TIME_PER_TICK),
+    };
+    let results = labels.iter().zip(progress.iter()).map(|(label, p)| CarResult::new(label, p)).collect::<Vec<_>>();
+    if json {
+        print_json(&results);
+    } else {
+        print_results(&results);
+    }
+}
+
+fn race(args: RaceArgs, json: bool) {
+    let track = load_track(args.track.as_deref());
+    let (labels, inputs) = load_entrants(&args.scripts);
+    let mut session = RaceSession::new(&track, args.physics.constants(), TIME_PER_TICK, labels.into_iter().zip(inputs).collect())
+        .unwrap_or_else(|e| fail(format!("Failed to set up the race: {}", e)));
+    if let Some(mode) = args.car_collisions {
+        let response = mode.response(args.restitution);
+        response.check().unwrap_or_else(|e| fail(format!("Invalid car collisions: {}", e)));
+        session = session.with_car_collisions(response);
+    }
+    let classification = session.run();
+    if json {
+        print_json(&classification);
+    } else {
+        print!("{}", classification);
+    }
+}
+
+fn validate_track(path: PathBuf, json: bool) {
+    let report = match load_track_or_preset(&path) {
+        Ok(track) => {
+            let validation = track.validate();
+            TrackReport {
+                valid: validation.is_valid(),
+                error: None,
+                name: Some(track.metadata.name.clone()),
+                sections: Some(track.sections().len()),
+                diagnostics: validation.diagnostics,
+                path,
+            }
+        }
+        Err(e) => TrackReport {
+            valid: false,
+            error: Some(e.to_string()),
+            name: None,
+            sections: None,
+            diagnostics: Vec::new(),
+            path,
+        },
+    };
+    if json {
+        print_json(&report);
+    } else if let Some(error) = &report.error {
+        println!("{}: invalid, {}", report.path.display(), error);
+    } else {
+        println!(
+            "{}: {} track {:?} with {} sections",
+            report.path.display(),
+            if report.valid { "valid" } else { "invalid" },
+            report.name.as_deref().unwrap_or_default(),
+            report.sections.unwrap_or_default()
+        );
+        print!("{}", Validation { diagnostics: report.diagnostics.clone() });
+    }
+    if !report.valid {
+        exit(1);
+    }
+}
+
+fn replay(path: PathBuf, track: Option<PathBuf>, json: bool) {
+    let replay = Replay::load(&path).unwrap_or_else(|e| fail(format!("Failed to load replay {}: {}", path.display(), e)));
+    let track = load_track(track.as_deref());
+    let report = match replay.verify(&track) {
+        Ok(progress) => ReplayReport {
+            verified: true,
+            error: None,
+            cars: replay.cars.iter().zip(progress.iter()).map(|(car, p)| CarResult::new(&car.label, p)).collect(),
+            path,
+        },
+        Err(e) => ReplayReport {
+            verified: false,
+            error: Some(e.to_string()),
+            cars: Vec::new(),
+            path,
+        },
+    };
+    if json {
+        print_json(&report);
+    } else if let Some(error) = &report.error {
+        println!("{}: failed, {}", report.path.display(), error);
+    } else {
+        print_results(&report.cars);
+        println!("{}: verified", report.path.display());
+    }
+    if !report.verified {
+        exit(1);
+    }
+}
+
+fn bench(args: BenchArgs, json: bool) {
+    let track = load_track(args.track.as_deref());
+    let physics = args.physics.constants();
+    let mut runner = BatchRunner::new(&track, TIME_PER_TICK);
+    if let Some(threads) = args.threads {
+        runner = runner.with_threads(threads);
+    }
+    let runs = (0..args.runs)
+        .flat_map(|_| fixed_keys(args.cars))
+        .map(|(label, key)| BatchRun::new(label, Box::new(SingleInput::from(key)), physics))
+        .collect();
+    let batch = runner.run(runs);
+    let report = BenchReport {
+        runs: args.runs,
+        cars: args.cars,
+        threads: batch.threads,
+        car_ticks: batch.car_ticks,
+        elapsed_s: batch.elapsed_s,
+        car_ticks_per_second: batch.car_ticks_per_second(),
+    };
+    if json {
+        print_json(&report);
+    } else {
+        println!(
+            "{} runs of {} cars on {} threads: {} car ticks in {:.3}s, {:.0} ticks/s",
+            report.runs, report.cars, report.threads, report.car_ticks, report.elapsed_s, report.car_ticks_per_second
+        );
+    }
+}
+
+fn generate_track(args: GenerateArgs, json: bool) {
+    let config = GeneratorConfig {
+        seed: args.seed,
+        length_m: args.length,
+        min_width_m: args.min_width,
+        max_width_m: args.max_width,
+        difficulty: args.difficulty,
+        closed: !args.open,
+        laps: args.laps,
+    };
+    let track = Track::generate(&config).unwrap_or_else(|e| fail(format!("Failed to generate a track: {}", e)));
+    track
+        .save(&args.path)
+        .unwrap_or_else(|e| fail(format!("Failed to save track {}: {}", args.path.display(), e)));
+    let report = GenerateReport {
+        path: args.path,
+        name: track.metadata.name.clone(),
+        length_m: track.race_length() / track.laps() as f32,
+        config,
+    };
+    if json {
+        print_json(&report);
+    } else {
+        println!(
+            "{}: generated {:?}, {:.0} m {}",
+            report.path.display(),
+            report.name,
+            report.length_m,
+            if report.config.closed { "loop" } else { "road" }
+        );
+    }
+}
+
+fn tracks(check: bool, json: bool) {
+    let physics = PhysicsConstants::default();
+    let reports = PRESETS
+        .iter()
+        .map(|preset| {
+            let run = check.then(|| preset.reference_run(&physics));
+            let time_s = run.as_ref().and_then(|run| run.end_time.map(|end| end - run.start_time));
+            PresetReport {
+                name: preset.name,
+                description: preset.description,
+                reference_time_s: preset.reference_time_s,
+                state: run.as_ref().map(|run| run.state),
+                time_s,
+                matches: run.as_ref().map(|run| {
+                    run.state == CarState::Finished
+                        && time_s.is_some_and(|time| (time - preset.reference_time_s).abs() <= REFERENCE_TOLERANCE_S)
+                }),
+            }
+        })
+        .collect::<Vec<_>>();
+    if json {
+        print_json(&reports);
+    } else {
+        for report in &reports {
+            print!("{:<16} {:>8.3}s", report.name, report.reference_time_s);
+            match (report.state, report.time_s, report.matches) {
+                (Some(CarState::Finished), Some(time), Some(matches)) => {
+                    print!("  now {:>8.3}s {}", time, if matches { "ok" } else { "CHANGED" })
+                }
+                (Some(state), _, _) => print!("  now {} CHANGED", state),
+                _ => {}
+            }
+            println!("  {}", report.description);
+        }
+    }
+    if reports.iter().any(|report| report.matches == Some(false)) {
+        exit(1);
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Simulate(args)) => simulate(args, cli.json),
+        Some(Command::Race(args)) => race(args, cli.json),
+        Some(Command::ValidateTrack { path }) => validate_track(path, cli.json),
+        Some(Command::Replay { path, track }) => replay(path, track, cli.json),
+        Some(Command::Bench(args)) => bench(args, cli.json),
+        Some(Command::GenerateTrack(args)) => generate_track(args, cli.json),
+        Some(Command::Tracks { check }) => tracks(check, cli.json),
+        None => simulate(SimulateArgs::default(), cli.json),
     }
 }
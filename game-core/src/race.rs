@@ -0,0 +1,431 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::car::{Car, CarState, PhysicsConstants};
+use crate::car_progress::CarProgress;
+use crate::collision::{first_overlap_fraction, CarCollisionResponse, CarContact};
+use crate::input::{InputProvider, Observation};
+use crate::track::Track;
+
+#[derive(Debug)]
+pub enum RaceError {
+    // There are more entrants than slots on the track's grid
+    GridFull { slots: usize, entrants: usize },
+}
+
+impl fmt::Display for RaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaceError::GridFull { slots, entrants } => {
+                write!(f, "the track's grid has {} slots but there are {} entrants", slots, entrants)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RaceError {}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum DnfReason {
+    Crashed,
+    TimedOut,
+}
+
+impl DnfReason {
+    // None for cars that finished or are still running
+    pub fn from_state(state: CarState) -> Option<DnfReason> {
+        match state {
+            CarState::Crashed => Some(DnfReason::Crashed),
+            CarState::TimedOut => Some(DnfReason::TimedOut),
+            CarState::StartLine | CarState::Racing | CarState::Finished => None,
+        }
+    }
+}
+
+impl fmt::Display for DnfReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnfReason::Crashed => f.write_str("crashed"),
+            DnfReason::TimedOut => f.write_str("timed out"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum RaceOutcome {
+    // `gap_s` is behind the winner, so 0 for the winner themselves
+    Finished { time_s: f32, gap_s: f32 },
+    DidNotFinish(DnfReason),
+    Running,
+}
+
+pub struct RaceEntry {
+    pub car: Car,
+    pub input: Box<dyn InputProvider>,
+    pub progress: CarProgress,
+    // 0 is pole
+    pub grid_slot: usize,
+}
+
+impl RaceEntry {
+    fn race_time(&self) -> Option<f32> {
+        match self.car.state {
+            CarState::Finished => self.progress.end_time.map(|end| end - self.progress.start_time),
+            _ => None,
+        }
+    }
+
+    // Finishers by time, then everyone else by the furthest they got along the racing line
+    fn compare(&self, other: &RaceEntry) -> Ordering {
+        match (self.race_time(), other.race_time()) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => other.progress.best_distance_m.total_cmp(&self.progress.best_distance_m),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RaceResult {
+    // 1 is the leader
+    pub position: usize,
+    pub label: String,
+    pub grid_slot: usize,
+    pub outcome: RaceOutcome,
+    pub laps_completed: u32,
+    pub progress: CarProgress,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Classification {
+    pub results: Vec<RaceResult>,
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>3}  {:<16} {:>4} {:>4}  Result", "Pos", "Car", "Grid", "Laps")?;
+        for result in &self.results {
+            let outcome = match result.outcome {
+                RaceOutcome::Finished { time_s, .. } if result.position == 1 => format!("{:.4}", time_s),
+                RaceOutcome::Finished { gap_s, .. } => format!("+{:.4}", gap_s),
+                RaceOutcome::DidNotFinish(reason) => {
+                    format!("DNF, {} at {:.0}%", reason, result.progress.completion * 100.0)
+                }
+                RaceOutcome::Running => format!("running, {:.0}%", result.progress.completion * 100.0),
+            };
+            writeln!(
+                f,
+                "{:>3}  {:<16} {:>4} {:>4}  {}",
+                result.position,
+                result.label,
+                result.grid_slot + 1,
+                result.laps_completed,
+                outcome
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// A race between several cars, each starting from its own grid slot
+pub struct RaceSession<'a> {
+    pub track: &'a Track,
+    pub physics: PhysicsConstants,
+    pub time_per_tick_s: f32,
+    pub entries: Vec<RaceEntry>,
+    // Without a response cars pass through each other unnoticed
+    pub car_collisions: Option<CarCollisionResponse>,
+    tick: u64,
+    time_s: f32,
+    // Pairs of entries that were touching at the end of the last tick
+    touching: Vec<(usize, usize)>,
+}
+
+impl<'a> RaceSession<'a> {
+    // Entrants take the grid in the order given
+    pub fn new(
+        track: &'a Track,
+        physics: PhysicsConstants,
+        time_per_tick_s: f32,
+        entrants: Vec<(String, Box<dyn InputProvider>)>,
+    ) -> Result<RaceSession<'a>, RaceError> {
+        let entrant_count = entrants.len();
+        let entries = entrants
+            .into_iter()
+            .enumerate()
+            .map(|(grid_slot, (label, input))| {
+                let slot = track.grid_slot(grid_slot).ok_or_else(|| RaceError::GridFull {
+                    slots: track.grid_size(),
+                    entrants: entrant_count,
+                })?;
+                Ok(RaceEntry {
+                    car: Car::on_grid(&slot, &label),
+                    input,
+                    progress: CarProgress::new(0.0),
+                    grid_slot,
+                })
+            })
+            .collect::<Result<Vec<_>, RaceError>>()?;
+        Ok(RaceSession {
+            track,
+            physics,
+            time_per_tick_s,
+            entries,
+            car_collisions: None,
+            tick: 0,
+            time_s: 0.0,
+            touching: Vec::new(),
+        })
+    }
+
+    pub fn with_car_collisions(mut self, response: CarCollisionResponse) -> RaceSession<'a> {
+        self.car_collisions = Some(response);
+        self
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn time_s(&self) -> f32 {
+        self.time_s
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.entries.iter().all(|entry| !entry.car.is_on_track())
+    }
+
+    // Moves every car on by one tick. Cars all see the track as it was at the start of the tick
+    pub fn step(&mut self) {
+        for entry in self.entries.iter_mut() {
+            let observation = Observation::new(&entry.car, self.track, self.tick, self.time_s);
+            let input = entry.input.get_input(&observation);
+            entry.car.update_position(&self.physics, self.time_per_tick_s, Some(input));
+        }
+        for entry in self.entries.iter_mut() {
+            entry
+                .car
+                .update_state(self.track, &mut entry.progress, self.time_s, self.time_per_tick_s);
+        }
+        if let Some(response) = self.car_collisions {
+            self.collide_cars(response);
+        }
+        self.tick += 1;
+        self.time_s += self.time_per_tick_s;
+    }
+
+    // Cars on the grid or racing can hit each other, anyone else has left the track
+    fn collide_cars(&mut self, response: CarCollisionResponse) {
+        let mut touching = Vec::new();
+        for j in 1..self.entries.len() {
+            let (before, rest) = self.entries.split_at_mut(j);
+            let b = &mut rest[0];
+            for (i, a) in before.iter_mut().enumerate() {
+                if !a.car.is_on_track() || !b.car.is_on_track() {
+                    continue;
+                }
+                let Some((normal, depth)) = a.car.body().overlap(&b.car.body()) else {
+                    continue;
+                };
+                touching.push((i, j));
+                if !self.touching.contains(&(i, j)) {
+                    let (a_label, b_label) = (a.car.label.clone(), b.car.label.clone());
+                    for (entry, other) in [(&mut *a, b_label), (&mut *b, a_label)] {
+                        entry.progress.car_contacts.push(CarContact { tick: self.tick, other });
+                    }
+                }
+                match response {
+                    CarCollisionResponse::Ghost => {}
+                    CarCollisionResponse::Bounce { restitution } => {
+                        a.car.bounce_off(&mut b.car, self.track, normal, depth, restitution);
+                    }
+                    CarCollisionResponse::CrashBoth => {
+                        let fraction = first_overlap_fraction(
+                            (&a.car.previous_body(), &a.car.body()),
+                            (&b.car.previous_body(), &b.car.body()),
+                        );
+                        let crashed_at_s = self.time_s + fraction * self.time_per_tick_s;
+                        a.car.crash(&mut a.progress, crashed_at_s);
+                        b.car.crash(&mut b.progress, crashed_at_s);
+                    }
+                }
+            }
+        }
+        self.touching = touching;
+    }
+
+    // Races until every car has finished or dropped out and returns the final classification
+    pub fn run(&mut self) -> Classification {
+        while !self.is_over() {
+            self.step();
+        }
+        self.standings()
+    }
+
+    // The running order right now, which is the classification once the race is over
+    pub fn standings(&self) -> Classification {
+        let mut order = self.entries.iter().collect::<Vec<_>>();
+        order.sort_by(|a, b| a.compare(b));
+        let winner_time = order.first().and_then(|entry| entry.race_time());
+        let results = order
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let outcome = match (entry.race_time(), winner_time) {
+                    (Some(time_s), Some(winner_time)) => RaceOutcome::Finished {
+                        time_s,
+                        gap_s: time_s - winner_time,
+                    },
+                    _ => DnfReason::from_state(entry.car.state).map_or(RaceOutcome::Running, RaceOutcome::DidNotFinish),
+                };
+                RaceResult {
+                    position: i + 1,
+                    label: entry.car.label.clone(),
+                    grid_slot: entry.grid_slot,
+                    outcome,
+                    laps_completed: entry.progress.laps_completed,
+                    progress: entry.progress.clone(),
+                }
+            })
+            .collect();
+        Classification { results }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    use crate::car::TerminationCondition;
+    use crate::default_tracks::{self, PRESETS};
+    use crate::gameloop::TIME_PER_TICK;
+    use crate::gate::SegmentGate;
+    use crate::input::AnalogInput;
+    use crate::line_follower::LineFollower;
+    use crate::coordinates::Vec2d;
+    use crate::track::{GridSlot, ParallelRectSection, TrackMetadata, GRID_SLOTS};
+
+    #[test]
+    fn every_preset_has_a_full_grid_on_the_track() {
+        for preset in PRESETS {
+            let track = preset.build();
+            for i in 0..track.grid_size() {
+                assert!(track.has_room_for(&track.grid_slot(i).unwrap()), "{} slot {}", preset.name, i);
+            }
+            let slots = if preset.name == "narrow-maze" { 4 } else { GRID_SLOTS };
+            assert_eq!(track.grid_size(), slots, "{}", preset.name);
+        }
+    }
+
+    #[test]
+    fn the_default_grid_leaves_out_slots_off_the_track() {
+        let mut track = default_tracks::make_track();
+        track.grid.clear();
+        // Only just wide enough for the pole's column, with two rows behind the start
+        track.set_sections(vec![Box::new(ParallelRectSection {
+            left_x: -2.0,
+            right_x: 2.0,
+            top_y: 380.0,
+            bottom_y: -19.0,
+        })]);
+        assert_eq!(track.grid_size(), 3);
+        assert_eq!(track.grid_slot(2).map(|slot| slot.pos), Some(Vec2d::new(0.0, -16.0)));
+        assert!(track.grid_slot(3).is_none());
+    }
+
+    #[test]
+    fn cars_all_start_on_an_open_road() {
+        let track = default_tracks::preset("chicane").unwrap().build();
+        let physics = PhysicsConstants::default();
+        let entrants = (0..4)
+            .map(|i| (format!("car {}", i), Box::new(LineFollower::new(physics)) as Box<dyn InputProvider>))
+            .collect();
+        let classification = RaceSession::new(&track, physics, TIME_PER_TICK, entrants).unwrap().run();
+        for result in &classification.results {
+            assert!(matches!(result.outcome, RaceOutcome::Finished { .. }), "{:?}", result);
+        }
+    }
+
+    // Two cars facing each other twenty metres apart on open ground
+    fn head_on() -> Track {
+        let ground = ParallelRectSection {
+            left_x: -50.0,
+            right_x: 50.0,
+            top_y: 200.0,
+            bottom_y: -50.0,
+        };
+        Track::new(
+            TrackMetadata::default(),
+            Vec2d::new(0.0, 0.0),
+            0.0,
+            SegmentGate::across(Vec2d::new(0.0, 150.0), 100.0, 0.0).into(),
+            vec![Box::new(ground)],
+            TerminationCondition::Seconds(100.0),
+        )
+        .with_grid(vec![
+            GridSlot { pos: Vec2d::new(0.0, 0.0), direction_radians: 0.0 },
+            GridSlot { pos: Vec2d::new(0.0, 20.0), direction_radians: PI },
+        ])
+    }
+
+    fn closing_in() -> Vec<(String, Box<dyn InputProvider>)> {
+        let flat_out = |_: &Observation| AnalogInput { throttle: 1.0, ..AnalogInput::default() };
+        vec![("up".to_string(), Box::new(flat_out)), ("down".to_string(), Box::new(flat_out))]
+    }
+
+    #[test]
+    fn cars_crashing_into_each_other_stop_the_clock_when_they_touch() {
+        let track = head_on();
+        let mut session = RaceSession::new(&track, PhysicsConstants::default(), TIME_PER_TICK, closing_in())
+            .unwrap()
+            .with_car_collisions(CarCollisionResponse::CrashBoth);
+        session.run();
+
+        let (up, down) = (&session.entries[0].progress, &session.entries[1].progress);
+        assert_eq!((up.state, down.state), (CarState::Crashed, CarState::Crashed));
+        assert_eq!(up.car_contacts, vec![CarContact { tick: down.car_contacts[0].tick, other: "down".to_string() }]);
+        assert_eq!(down.car_contacts.len(), 1);
+        assert_eq!((up.end_time, up.end_tick), (down.end_time, down.end_tick));
+        // Part way through the tick they met on, not at the end of it
+        let tick_start_s = up.car_contacts[0].tick as f32 * TIME_PER_TICK;
+        let end_time = up.end_time.unwrap();
+        assert!(end_time > tick_start_s + 1e-4 && end_time < tick_start_s + TIME_PER_TICK - 1e-4, "{end_time}");
+    }
+
+    #[test]
+    fn contacts_are_logged_once_each_however_long_cars_keep_touching() {
+        let track = head_on();
+        // Bouncing flat out into each other they keep pushing back into contact, ghosting through
+        // each other they overlap for several ticks on end
+        for response in [CarCollisionResponse::Bounce { restitution: 0.0 }, CarCollisionResponse::Ghost] {
+            let mut session = RaceSession::new(&track, PhysicsConstants::default(), TIME_PER_TICK, closing_in())
+                .unwrap()
+                .with_car_collisions(response);
+            let (mut new_contacts, mut ticks_touching) = (Vec::new(), 0);
+            for _ in 0..300 {
+                let was_touching = !session.touching.is_empty();
+                session.step();
+                if !session.touching.is_empty() {
+                    ticks_touching += 1;
+                    if !was_touching {
+                        new_contacts.push(session.tick() - 1);
+                    }
+                }
+            }
+
+            match response {
+                CarCollisionResponse::Ghost => assert!(new_contacts.len() == 1 && ticks_touching > 1),
+                _ => assert!(new_contacts.len() > 1),
+            }
+            for (entry, other) in session.entries.iter().zip(["down", "up"]) {
+                assert_eq!(entry.car.state, CarState::Racing);
+                let logged = entry.progress.car_contacts.iter().map(|contact| (contact.tick, contact.other.as_str()));
+                assert!(logged.eq(new_contacts.iter().map(|tick| (*tick, other))), "{:?}", response);
+            }
+        }
+    }
+}
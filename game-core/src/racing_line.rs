@@ -0,0 +1,147 @@
+use crate::centerline::{nearest_on_piece, CenterlineSection};
+use crate::coordinates::Vec2d;
+use crate::track::Track;
+
+// The path progress is measured along, from the start through the middle of every gate in order
+// to the finish. On a track raced over several laps it goes from the finish line round to the
+// finish line again instead, so it's as long as a lap and its ends meet. On a track laid along a
+// centerline it follows the road between gates, otherwise it cuts straight across
+#[derive(Clone, Debug, Default)]
+pub struct RacingLine {
+    points: Vec<Vec2d>,
+    // Arc length from the start to each point
+    distances: Vec<f32>,
+    // Arc length from the start to each gate, in the order of `Track::gate`
+    gate_distances: Vec<f32>,
+}
+
+impl RacingLine {
+    pub fn new(track: &Track) -> RacingLine {
+        let centerline = track.sections().iter().find_map(|section| section.centerline());
+        let first = if track.laps() > 1 {
+            track.finish_line().centre_near(&track.start())
+        } else {
+            track.start()
+        };
+        let mut line = RacingLine {
+            points: vec![first],
+            distances: vec![0.0],
+            gate_distances: Vec::with_capacity(track.sectors_per_lap()),
+        };
+        for i in 0..track.sectors_per_lap() {
+            let from = line.points[line.points.len() - 1];
+            let centre = track.gate(i).centre_near(&from);
+            if let Some(section) = centerline {
+                line.follow(section, from, centre);
+            }
+            line.push(centre);
+            line.gate_distances.push(line.lap_length());
+        }
+        line
+    }
+
+    fn push(&mut self, point: Vec2d) {
+        let distance = self.lap_length() + self.points.last().map_or(0.0, |last| last.distance(point));
+        self.points.push(point);
+        self.distances.push(distance);
+    }
+
+    // Adds the centerline's samples that lie between two points on it, going forwards round a loop
+    fn follow(&mut self, section: &CenterlineSection, from: Vec2d, to: Vec2d) {
+        let (start, mut end) = (section.project(&from), section.project(&to));
+        let passes = if section.centerline.closed {
+            if end <= start {
+                end += section.length();
+            }
+            2
+        } else {
+            1
+        };
+        for pass in 0..passes {
+            let offset = pass as f32 * section.length();
+            for sample in section.samples() {
+                let distance = sample.distance + offset;
+                if distance > start && distance < end {
+                    self.push(sample.pos);
+                }
+            }
+        }
+    }
+
+    pub fn points(&self) -> &[Vec2d] {
+        &self.points
+    }
+
+    // From the start, or from the finish line on a track raced over several laps, to the finish line
+    pub fn lap_length(&self) -> f32 {
+        self.distances.last().copied().unwrap_or_default()
+    }
+
+    // The point `distance` along the line, held at either end
+    pub fn point_at(&self, distance: f32) -> Vec2d {
+        let i = self.distances.partition_point(|d| *d <= distance);
+        if i == 0 {
+            return self.points.first().copied().unwrap_or_default();
+        }
+        if i == self.points.len() {
+            return self.points[i - 1];
+        }
+        let (start, end) = (self.distances[i - 1], self.distances[i]);
+        let t = if end > start { (distance - start) / (end - start) } else { 0.0 };
+        self.points[i - 1].lerp(self.points[i], t)
+    }
+
+    // How far along the line `pos` is. Only the stretch leading up to gate `next_gate` is
+    // considered, so a car is never credited with a part of the lap it hasn't reached yet even
+    // where the road doubles back on itself
+    pub fn distance_at(&self, pos: &Vec2d, next_gate: usize) -> f32 {
+        let from = next_gate
+            .checked_sub(1)
+            .and_then(|i| self.gate_distances.get(i))
+            .copied()
+            .unwrap_or_default();
+        let to = self.gate_distances.get(next_gate).copied().unwrap_or(self.lap_length());
+        let mut best = (f32::MAX, from);
+        for i in 0..self.points.len().saturating_sub(1) {
+            let (piece_start, piece_end) = (self.distances[i], self.distances[i + 1]);
+            if piece_end < from || piece_start > to {
+                continue;
+            }
+            let (nearest, t) = nearest_on_piece(self.points[i], self.points[i + 1], pos);
+            let gap = pos.distance(nearest);
+            if gap < best.0 {
+                best = (gap, (piece_start + t * (piece_end - piece_start)).clamp(from, to));
+            }
+        }
+        best.1
+    }
+}
+
+impl Track {
+    // Distance along the racing line covered over the whole race, counting laps already done
+    pub fn race_distance(&self, pos: &Vec2d, laps_completed: u32, next_gate: usize) -> f32 {
+        let line = self.racing_line();
+        laps_completed as f32 * line.lap_length() + line.distance_at(pos, next_gate)
+    }
+
+    pub fn race_length(&self) -> f32 {
+        self.laps() as f32 * self.racing_line().lap_length()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::default_tracks::PRESETS;
+
+    #[test]
+    fn lines_round_a_loop_run_from_the_finish_line_back_to_it() {
+        let preset = PRESETS.iter().find(|preset| preset.name == "oval").unwrap();
+        let track = preset.build();
+        let line = track.racing_line();
+        let points = line.points();
+        assert!(points[0].distance(points[points.len() - 1]) < 1e-3);
+        assert_eq!(track.race_length(), 3.0 * line.lap_length());
+        // The stretch from the grid up to the line isn't part of any lap
+        assert_eq!(track.race_distance(&track.start(), 0, 0), 0.0);
+    }
+}
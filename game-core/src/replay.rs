@@ -0,0 +1,355 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::car::{Car, CarState, PhysicsConstants};
+use crate::car_progress::CarProgress;
+use crate::gameloop::{run_until, run_until_observed};
+use crate::input::{AnalogInput, InputProvider, Observation};
+use crate::track::{Track, TrackMetadata};
+use crate::track_file::TrackFile;
+
+pub const REPLAY_VERSION: u32 = 1;
+
+// Which track a replay was recorded on. The hash covers the geometry and rules but not the
+// metadata, so renaming a track doesn't invalidate its replays. Tracks with sections a track file
+// can't hold are hashed by the edges of those sections instead
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackIdentity {
+    pub name: String,
+    pub hash: u64,
+}
+
+impl TrackIdentity {
+    pub fn of(track: &Track) -> TrackIdentity {
+        let contents = match TrackFile::from_track(track) {
+            Ok(mut file) => {
+                file.metadata = TrackMetadata::default();
+                serde_json::to_string(&file).expect("a track file always serializes to JSON")
+            }
+            Err(_) => {
+                let edges = track.sections().iter().map(|section| section.edges()).collect::<Vec<_>>();
+                format!(
+                    "{:?} {} {} {:?} {:?} {:?} {:?} {:?} {:?}",
+                    track.start(),
+                    track.start_direction_radians(),
+                    track.laps(),
+                    track.finish_line(),
+                    track.checkpoints(),
+                    track.grid,
+                    track.termination_condition,
+                    track.collision_response,
+                    edges
+                )
+            }
+        };
+        TrackIdentity {
+            name: track.metadata.name.clone(),
+            hash: fnv1a(contents.as_bytes()),
+        }
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// The same input held for a number of consecutive ticks
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputRun {
+    pub ticks: u32,
+    pub input: AnalogInput,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CarReplay {
+    pub label: String,
+    // Inputs from the first tick until the car stopped racing
+    pub inputs: Vec<InputRun>,
+    pub progress: CarProgress,
+}
+
+impl CarReplay {
+    fn push(&mut self, input: AnalogInput) {
+        match self.inputs.last_mut() {
+            Some(run) if run.input == input && run.ticks < u32::MAX => run.ticks += 1,
+            _ => self.inputs.push(InputRun { ticks: 1, input }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io { path: PathBuf, source: std::io::Error },
+    Json(String),
+    UnsupportedVersion(u32),
+    TrackMismatch { expected: TrackIdentity, found: TrackIdentity },
+    Diverged { label: String, expected: Box<CarProgress>, actual: Box<CarProgress> },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io { path, source } => write!(f, "could not access {}: {}", path.display(), source),
+            ReplayError::Json(message) => write!(f, "malformed replay: {}", message),
+            ReplayError::UnsupportedVersion(version) => write!(
+                f,
+                "replay version {} is newer than the supported version {}",
+                version, REPLAY_VERSION
+            ),
+            ReplayError::TrackMismatch { expected, found } => write!(
+                f,
+                "replay was recorded on {:?} ({:016x}) but the track is {:?} ({:016x})",
+                expected.name, expected.hash, found.name, found.hash
+            ),
+            ReplayError::Diverged { label, expected, actual } => write!(
+                f,
+                "car {} diverged: recorded {:?}, replayed {:?}",
+                label, expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+// Everything needed to rerun a race tick for tick: the track it was on, the physics, the tick
+// length and every car's inputs, along with the results to check the rerun against
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub track: TrackIdentity,
+    pub physics: PhysicsConstants,
+    pub time_per_tick_s: f32,
+    pub cars: Vec<CarReplay>,
+}
+
+impl Replay {
+    // Runs the race as `run_until` does, recording each car's inputs as it goes
+    pub fn record(
+        cars: Vec<(&mut Car, &mut Box<dyn InputProvider>)>,
+        track: &Track,
+        physics: &PhysicsConstants,
+        time_per_tick_s: f32,
+    ) -> (Vec<CarProgress>, Replay) {
+        let mut car_replays = cars
+            .iter()
+            .map(|(car, _)| CarReplay {
+                label: car.label.clone(),
+                inputs: Vec::new(),
+                progress: CarProgress::default(),
+            })
+            .collect::<Vec<_>>();
+        let progress = run_until_observed(cars, track, physics, time_per_tick_s, |index, car, input| {
+            if car.state == CarState::StartLine || car.state == CarState::Racing {
+                car_replays[index].push(*input);
+            }
+        });
+        for (car_replay, car_progress) in car_replays.iter_mut().zip(progress.iter()) {
+            car_replay.progress = car_progress.clone();
+        }
+        let replay = Replay {
+            version: REPLAY_VERSION,
+            track: TrackIdentity::of(track),
+            physics: *physics,
+            time_per_tick_s,
+            cars: car_replays,
+        };
+        (progress, replay)
+    }
+
+    // A replay of one car's run captured elsewhere, such as the interactive game. `inputs` holds
+    // the input for every tick the car moved and `progress` is how the run ended, which `verify`
+    // then checks a rerun against
+    pub fn from_run(
+        track: &Track,
+        physics: &PhysicsConstants,
+        time_per_tick_s: f32,
+        label: &str,
+        inputs: impl IntoIterator<Item = AnalogInput>,
+        progress: CarProgress,
+    ) -> Replay {
+        let mut car_replay = CarReplay {
+            label: label.to_string(),
+            inputs: Vec::new(),
+            progress,
+        };
+        inputs.into_iter().for_each(|input| car_replay.push(input));
+        Replay {
+            version: REPLAY_VERSION,
+            track: TrackIdentity::of(track),
+            physics: *physics,
+            time_per_tick_s,
+            cars: vec![car_replay],
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Replay, ReplayError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|source| ReplayError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let replay: Replay = serde_json::from_str(&contents).map_err(|e| ReplayError::Json(e.to_string()))?;
+        if replay.version > REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(replay.version));
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        let path = path.as_ref();
+        let contents = serde_json::to_string(self).map_err(|e| ReplayError::Json(e.to_string()))?;
+        fs::write(path, contents).map_err(|source| ReplayError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    // Reruns every car from the start line and checks it ends up exactly where it did when the
+    // replay was recorded
+    pub fn verify(&self, track: &Track) -> Result<Vec<CarProgress>, ReplayError> {
+        let found = TrackIdentity::of(track);
+        if found.hash != self.track.hash {
+            return Err(ReplayError::TrackMismatch {
+                expected: self.track.clone(),
+                found,
+            });
+        }
+        let mut cars = self
+            .cars
+            .iter()
+            .map(|car_replay| Car::on_start_line(track, &car_replay.label))
+            .collect::<Vec<_>>();
+        let mut inputs = self
+            .cars
+            .iter()
+            .map(|car_replay| Box::new(ReplayInput::new(car_replay)) as Box<dyn InputProvider>)
+            .collect::<Vec<_>>();
+        let car_input = cars.iter_mut().zip(inputs.iter_mut()).collect::<Vec<_>>();
+        let progress = run_until(car_input, track, &self.physics, self.time_per_tick_s);
+        for (car_replay, actual) in self.cars.iter().zip(progress.iter()) {
+            if !same_bits(&car_replay.progress, actual) {
+                return Err(ReplayError::Diverged {
+                    label: car_replay.label.clone(),
+                    expected: Box::new(car_replay.progress.clone()),
+                    actual: Box::new(actual.clone()),
+                });
+            }
+        }
+        Ok(progress)
+    }
+}
+
+// JSON floats are written in their shortest round-tripping form, so equal text means equal bits
+fn same_bits(a: &CarProgress, b: &CarProgress) -> bool {
+    serde_json::to_string(a).ok() == serde_json::to_string(b).ok()
+}
+
+// Plays back one car's recorded inputs by tick, then no input once they run out
+#[derive(Clone, Debug)]
+pub struct ReplayInput {
+    inputs: Vec<InputRun>,
+    // Tick each run starts on
+    run_starts: Vec<u64>,
+}
+
+impl ReplayInput {
+    pub fn new(car_replay: &CarReplay) -> ReplayInput {
+        let run_starts = car_replay
+            .inputs
+            .iter()
+            .scan(0u64, |tick, run| {
+                let start = *tick;
+                *tick += run.ticks as u64;
+                Some(start)
+            })
+            .collect();
+        ReplayInput {
+            inputs: car_replay.inputs.clone(),
+            run_starts,
+        }
+    }
+}
+
+impl InputProvider for ReplayInput {
+    fn get_input(&mut self, observation: &Observation) -> AnalogInput {
+        let index = self.run_starts.partition_point(|start| *start <= observation.tick);
+        match index.checked_sub(1).map(|i| (self.run_starts[i], &self.inputs[i])) {
+            Some((start, run)) if observation.tick < start + run.ticks as u64 => run.input,
+            _ => AnalogInput::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default_tracks::{self, make_track};
+    use crate::gameloop::TIME_PER_TICK;
+    use crate::input::KeyInput;
+    use crate::script::{ScriptTime, ScriptedInput};
+
+    fn record(track: &Track) -> Replay {
+        let physics = PhysicsConstants::default();
+        let mut cars = [Car::on_start_line(track, "a"), Car::on_start_line(track, "b")];
+        let mut inputs: Vec<Box<dyn InputProvider>> = vec![
+            Box::new(ScriptedInput::default().hold(ScriptTime::Ticks(0), KeyInput::from_directions(true, false, false, false))),
+            Box::new(
+                ScriptedInput::default()
+                    .hold(ScriptTime::Seconds(1.5), KeyInput::from_directions(true, false, false, false))
+                    .hold(ScriptTime::Ticks(0), KeyInput::from_directions(true, false, false, true)),
+            ),
+        ];
+        let car_input = cars.iter_mut().zip(inputs.iter_mut()).collect::<Vec<_>>();
+        Replay::record(car_input, track, &physics, TIME_PER_TICK).1
+    }
+
+    #[test]
+    fn saved_replays_verify() {
+        let track = make_track();
+        let replay = record(&track);
+        assert_eq!(replay.cars[0].progress.state, CarState::Finished);
+        assert_eq!(replay.cars[1].progress.state, CarState::Crashed);
+
+        let path = std::env::temp_dir().join(format!("replay-test-{}.json", std::process::id()));
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let progress = loaded.verify(&track).unwrap();
+        assert!(same_bits(&progress[0], &replay.cars[0].progress));
+        assert!(same_bits(&progress[1], &replay.cars[1].progress));
+    }
+
+    #[test]
+    fn runs_captured_elsewhere_verify() {
+        let track = make_track();
+        let recorded = record(&track);
+        let car = &recorded.cars[1];
+        let inputs = car.inputs.iter().flat_map(|run| (0..run.ticks).map(|_| run.input));
+        let replay = Replay::from_run(&track, &recorded.physics, TIME_PER_TICK, "b", inputs, car.progress.clone());
+        assert!(replay.verify(&track).is_ok());
+    }
+
+    #[test]
+    fn changed_inputs_diverge() {
+        let track = make_track();
+        let mut replay = record(&track);
+        replay.cars[1].inputs[0].ticks += 1;
+        match replay.verify(&track) {
+            Err(ReplayError::Diverged { label, .. }) => assert_eq!(label, "b"),
+            other => panic!("expected the replay to diverge, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn other_tracks_are_refused() {
+        let replay = record(&make_track());
+        let other = default_tracks::preset("chicane").unwrap().build();
+        assert!(matches!(replay.verify(&other), Err(ReplayError::TrackMismatch { .. })));
+    }
+}
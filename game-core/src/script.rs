@@ -0,0 +1,268 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::input::{AnalogInput, InputProvider, KeyInput, Observation};
+
+// Game time is accumulated a tick at a time, so allow for a little rounding when a segment is
+// measured in seconds
+const SECONDS_TOLERANCE: f32 = 1e-4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ScriptTime {
+    Ticks(u64),
+    Seconds(f32),
+}
+
+impl ScriptTime {
+    fn has_elapsed(&self, ticks: u64, seconds: f32) -> bool {
+        match self {
+            ScriptTime::Ticks(max_ticks) => ticks >= *max_ticks,
+            ScriptTime::Seconds(max_s) => seconds + SECONDS_TOLERANCE >= *max_s,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScriptSegment {
+    pub duration: ScriptTime,
+    pub input: AnalogInput,
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io { path: PathBuf, source: std::io::Error },
+    Json(String),
+    Line { line: usize, message: String },
+    Empty,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Io { path, source } => write!(f, "could not read {}: {}", path.display(), source),
+            ScriptError::Json(message) => write!(f, "malformed JSON input script: {}", message),
+            ScriptError::Line { line, message } => write!(f, "line {}: {}", line, message),
+            ScriptError::Empty => f.write_str("the input script has no segments"),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+// Plays back a list of inputs, each held for its duration. Once the script runs out the last
+// input is held until the run ends, so "accelerate for 2s, steer left for 0.5s, then brake" is
+//
+//     ScriptedInput::default()
+//         .hold(ScriptTime::Seconds(2.0), KeyInput::from_directions(true, false, false, false))
+//         .hold(ScriptTime::Seconds(0.5), KeyInput::from_directions(false, false, true, false))
+//         .hold(ScriptTime::Ticks(0), KeyInput::from_directions(false, true, false, false))
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedInput {
+    pub segments: Vec<ScriptSegment>,
+    current: usize,
+    // Tick and time at which the current segment started
+    segment_start: Option<(u64, f32)>,
+}
+
+impl ScriptedInput {
+    pub fn new(segments: Vec<ScriptSegment>) -> ScriptedInput {
+        ScriptedInput {
+            segments,
+            ..Default::default()
+        }
+    }
+
+    // Goes back to the first segment, ready for another run
+    pub fn reset(&mut self) {
+        self.current = 0;
+        self.segment_start = None;
+    }
+
+    pub fn hold(mut self, duration: ScriptTime, input: impl Into<AnalogInput>) -> ScriptedInput {
+        self.segments.push(ScriptSegment {
+            duration,
+            input: input.into(),
+        });
+        self
+    }
+
+    // `.json` files hold a list of `ScriptSegment`s, anything else is read as text with one
+    // segment per line: a duration (`120` ticks or `0.5s`) then the keys held, any of `UDLR` or `-`
+    // for none, separated by whitespace or a comma. Blank lines and `#` comments are skipped
+    pub fn load(path: impl AsRef<Path>) -> Result<ScriptedInput, ScriptError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|source| ScriptError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let segments = if is_json {
+            serde_json::from_str(&contents).map_err(|e| ScriptError::Json(e.to_string()))?
+        } else {
+            ScriptedInput::parse_text(&contents)?
+        };
+        if segments.is_empty() {
+            return Err(ScriptError::Empty);
+        }
+        Ok(ScriptedInput::new(segments))
+    }
+
+    pub fn parse_text(contents: &str) -> Result<Vec<ScriptSegment>, ScriptError> {
+        let mut segments = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line_err = |message: String| ScriptError::Line { line: i + 1, message };
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|field| !field.is_empty())
+                .collect();
+            let [duration, keys] = fields[..] else {
+                return Err(line_err(format!("expected a duration and keys, got {:?}", line)));
+            };
+            let duration = match duration.strip_suffix('s') {
+                Some(seconds) => seconds.parse().map(ScriptTime::Seconds).ok(),
+                None => duration.parse().map(ScriptTime::Ticks).ok(),
+            }
+            .ok_or_else(|| line_err(format!("{:?} isn't a number of ticks or seconds", duration)))?;
+            segments.push(ScriptSegment {
+                duration,
+                input: parse_keys(keys).map_err(line_err)?.into(),
+            });
+        }
+        Ok(segments)
+    }
+}
+
+fn parse_keys(keys: &str) -> Result<KeyInput, String> {
+    if keys == "-" {
+        return Ok(KeyInput::default());
+    }
+    let (mut up, mut down, mut left, mut right) = (false, false, false, false);
+    for key in keys.chars() {
+        match key.to_ascii_uppercase() {
+            'U' => up = true,
+            'D' => down = true,
+            'L' => left = true,
+            'R' => right = true,
+            other => return Err(format!("unknown key {:?}, expected some of UDLR or -", other)),
+        }
+    }
+    Ok(KeyInput::from_directions(up, down, left, right))
+}
+
+impl InputProvider for ScriptedInput {
+    fn get_input(&mut self, observation: &Observation) -> AnalogInput {
+        // The clock going backwards means the provider is being reused for a new run
+        if self.segment_start.is_some_and(|(tick, _)| observation.tick < tick) {
+            self.reset();
+        }
+        let mut start = *self.segment_start.get_or_insert((observation.tick, observation.time_s));
+        while self.current + 1 < self.segments.len()
+            && self.segments[self.current]
+                .duration
+                .has_elapsed(observation.tick - start.0, observation.time_s - start.1)
+        {
+            self.current += 1;
+            start = (observation.tick, observation.time_s);
+        }
+        self.segment_start = Some(start);
+        self.segments.get(self.current).map_or(AnalogInput::default(), |s| s.input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::{Car, CarState, PhysicsConstants};
+    use crate::default_tracks::make_track;
+    use crate::gameloop::{run_until, TIME_PER_TICK};
+
+    fn keys(up: bool, down: bool, left: bool, right: bool) -> AnalogInput {
+        KeyInput::from_directions(up, down, left, right).into()
+    }
+
+    // Accelerate for 2s, steer left for 0.5s, then brake
+    fn script() -> ScriptedInput {
+        ScriptedInput::default()
+            .hold(ScriptTime::Seconds(2.0), keys(true, false, false, false))
+            .hold(ScriptTime::Seconds(0.5), keys(false, false, true, false))
+            .hold(ScriptTime::Ticks(0), keys(false, true, false, false))
+    }
+
+    #[test]
+    fn text_scripts_parse() {
+        let segments = ScriptedInput::parse_text("# warm up\n2s U\n\n120, l  # turn\n1 ud\n5s -\n").unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                ScriptSegment {
+                    duration: ScriptTime::Seconds(2.0),
+                    input: keys(true, false, false, false),
+                },
+                ScriptSegment {
+                    duration: ScriptTime::Ticks(120),
+                    input: keys(false, false, true, false),
+                },
+                ScriptSegment {
+                    duration: ScriptTime::Ticks(1),
+                    input: keys(true, true, false, false),
+                },
+                ScriptSegment {
+                    duration: ScriptTime::Seconds(5.0),
+                    input: AnalogInput::default(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_lines_are_reported_by_number() {
+        for (contents, bad_line) in [("2s U\nsoon U", 2), ("2s U\n\n1 X", 3), ("2s", 1)] {
+            match ScriptedInput::parse_text(contents) {
+                Err(ScriptError::Line { line, .. }) => assert_eq!(line, bad_line, "{:?}", contents),
+                other => panic!("{:?} parsed as {:?}", contents, other),
+            }
+        }
+    }
+
+    #[test]
+    fn segments_change_on_the_tick_their_time_is_up() {
+        let track = make_track();
+        let car = Car::on_start_line(&track, "script");
+        let mut input = script();
+        let at = |input: &mut ScriptedInput, tick: u64| {
+            input.get_input(&Observation::new(&car, &track, tick, tick as f32 * TIME_PER_TICK))
+        };
+        assert_eq!(at(&mut input, 0), keys(true, false, false, false));
+        assert_eq!(at(&mut input, 479), keys(true, false, false, false));
+        assert_eq!(at(&mut input, 480), keys(false, false, true, false));
+        assert_eq!(at(&mut input, 599), keys(false, false, true, false));
+        assert_eq!(at(&mut input, 600), keys(false, true, false, false));
+        assert_eq!(at(&mut input, 10_000), keys(false, true, false, false));
+        // Starting again from tick 0 starts the script again
+        assert_eq!(at(&mut input, 0), keys(true, false, false, false));
+        assert_eq!(at(&mut input, 480), keys(false, false, true, false));
+    }
+
+    #[test]
+    fn accelerate_turn_and_brake() {
+        let track = make_track();
+        let physics = PhysicsConstants::default();
+        let mut car = Car::on_start_line(&track, "script");
+        let mut input: Box<dyn InputProvider> = Box::new(script());
+        let progress = run_until(vec![(&mut car, &mut input)], &track, &physics, TIME_PER_TICK).remove(0);
+        // The left turn points the car at the left wall, which it can't stop in time for
+        assert_eq!(progress.state, CarState::Crashed);
+        assert_eq!(progress.ticks, 1075);
+        assert_eq!(progress.end_tick, Some(1076));
+        let end_time = progress.end_time.unwrap();
+        assert!((end_time - 4.483).abs() < 1e-3, "crashed at {}", end_time);
+        assert!(car.pos.x < -45.0, "crashed at {:?}", car.pos);
+    }
+}
@@ -0,0 +1,147 @@
+use std::f32::consts::FRAC_PI_2;
+
+use serde::{Deserialize, Serialize};
+
+use crate::coordinates::{bounding_box, segment_intersection, Vec2d};
+use crate::spatial_index::IndexedEdge;
+use crate::track::Track;
+
+// Distances to the walls along rays fanned out from a car
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RaySensor {
+    // Relative to the car's heading, positive to the right as with headings
+    pub angles_radians: Vec<f32>,
+    pub max_length_m: f32,
+}
+
+impl RaySensor {
+    // `count` rays spread evenly from straight left to straight right
+    pub fn fan(count: usize, max_length_m: f32) -> RaySensor {
+        let angles_radians = match count {
+            0 => Vec::new(),
+            1 => vec![0.0],
+            _ => (0..count)
+                .map(|i| -FRAC_PI_2 + 2.0 * FRAC_PI_2 * i as f32 / (count - 1) as f32)
+                .collect(),
+        };
+        RaySensor {
+            angles_radians,
+            max_length_m,
+        }
+    }
+
+    pub fn cast(&self, track: &Track, pos: Vec2d, direction_radians: f32) -> Vec<RayHit> {
+        self.angles_radians
+            .iter()
+            .map(|angle| {
+                let heading = direction_radians + angle;
+                let distance_m = track.cast_ray(pos, heading, self.max_length_m);
+                RayHit {
+                    end: pos + Vec2d::from_heading(heading) * distance_m,
+                    distance_m,
+                    hit_wall: distance_m < self.max_length_m,
+                }
+            })
+            .collect()
+    }
+
+    pub fn distances(&self, track: &Track, pos: Vec2d, direction_radians: f32) -> Vec<f32> {
+        self.angles_radians
+            .iter()
+            .map(|angle| track.cast_ray(pos, direction_radians + angle, self.max_length_m))
+            .collect()
+    }
+}
+
+impl Default for RaySensor {
+    fn default() -> Self {
+        RaySensor::fan(7, 100.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayHit {
+    // Where the ray stopped, on the wall or at its full length
+    pub end: Vec2d,
+    pub distance_m: f32,
+    pub hit_wall: bool,
+}
+
+impl Track {
+    // Distance from `origin` to the first wall straight ahead along `heading_radians`, or
+    // `max_length_m` if there isn't one that close. Joins between sections aren't walls
+    pub fn cast_ray(&self, origin: Vec2d, heading_radians: f32, max_length_m: f32) -> f32 {
+        let end = origin + Vec2d::from_heading(heading_radians) * max_length_m;
+        let (min, max) = bounding_box([origin, end]).expect("a ray has two ends");
+        let mut nearest = 1.0;
+        for IndexedEdge { edge, .. } in self.spatial_index().edges_near(min, max) {
+            if let Some((t, _)) = segment_intersection(origin, end, edge.0, edge.1) {
+                if t < nearest && self.is_wall_at(origin.lerp(end, t), *edge) {
+                    nearest = t;
+                }
+            }
+        }
+        nearest * max_length_m
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::TerminationCondition;
+    use crate::gate::SegmentGate;
+    use crate::track::{ParallelRectSection, TrackMetadata, TrackSection};
+
+    fn track_of(sections: Vec<Box<dyn TrackSection + Send + Sync>>) -> Track {
+        Track::new(
+            TrackMetadata::default(),
+            Vec2d::new(0.0, 0.0),
+            0.0,
+            SegmentGate::across(Vec2d::new(0.0, 30.0), 20.0, 0.0).into(),
+            sections,
+            TerminationCondition::Seconds(100.0),
+        )
+    }
+
+    fn rect(left_x: f32, right_x: f32, bottom_y: f32, top_y: f32) -> Box<dyn TrackSection + Send + Sync> {
+        Box::new(ParallelRectSection {
+            left_x,
+            right_x,
+            top_y,
+            bottom_y,
+        })
+    }
+
+    #[test]
+    fn rays_stop_at_the_walls_around_the_car() {
+        let track = track_of(vec![rect(-10.0, 20.0, -5.0, 40.0)]);
+        // Straight left, ahead and straight right of a car facing up the rectangle
+        let sensor = RaySensor::fan(3, 100.0);
+        let hits = sensor.cast(&track, Vec2d::new(0.0, 0.0), 0.0);
+        let expected = [(-10.0, 0.0), (0.0, 40.0), (20.0, 0.0)].map(|(x, y)| Vec2d::new(x, y));
+        for (hit, end) in hits.iter().zip(expected) {
+            assert!(hit.hit_wall);
+            assert!((hit.end - end).length() < 1e-3, "{:?}", hit);
+            assert!((hit.distance_m - end.length()).abs() < 1e-3, "{:?}", hit);
+        }
+
+        // Rays that run out before a wall stop at their full length
+        let short = RaySensor::fan(3, 15.0);
+        let hits = short.cast(&track, Vec2d::new(0.0, 0.0), 0.0);
+        assert_eq!(hits.iter().map(|hit| hit.hit_wall).collect::<Vec<_>>(), vec![true, false, false]);
+        assert!((hits[0].distance_m - 10.0).abs() < 1e-3);
+        assert_eq!((hits[1].distance_m, hits[2].distance_m), (15.0, 15.0));
+        let distances = hits.iter().map(|hit| hit.distance_m).collect::<Vec<_>>();
+        assert_eq!(short.distances(&track, Vec2d::new(0.0, 0.0), 0.0), distances);
+    }
+
+    #[test]
+    fn rays_pass_through_joins_between_sections() {
+        // One rectangle overlapping the next, then one just touching it
+        for (near_top_y, far_bottom_y) in [(50.0, 40.0), (50.0, 50.0)] {
+            let track = track_of(vec![rect(-10.0, 10.0, -5.0, near_top_y), rect(-10.0, 10.0, far_bottom_y, 100.0)]);
+            let distance_m = track.cast_ray(Vec2d::new(0.0, 0.0), 0.0, 200.0);
+            assert!((distance_m - 100.0).abs() < 1e-3, "{} {}", far_bottom_y, distance_m);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::coordinates::{bounding_box, Vec2d};
+use crate::track::TrackSection;
+
+// Cells are sized to hold roughly this many edges each, but by default there are never more than
+// MAX_CELLS of them however small the edges get
+pub(crate) const EDGES_PER_CELL: usize = 8;
+pub(crate) const MAX_CELLS: usize = 1 << 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct IndexedEdge {
+    // Index into `Track::sections` of the section the edge belongs to
+    pub section: usize,
+    pub edge: (Vec2d, Vec2d),
+}
+
+// A uniform grid over the track's bounding box. Each cell lists the sections and edges whose
+// bounding boxes overlap it, so a query only looks at what's nearby instead of the whole track
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SpatialIndex {
+    origin: Vec2d,
+    cell_m: f32,
+    columns: usize,
+    rows: usize,
+    section_cells: Vec<Vec<usize>>,
+    edge_cells: Vec<Vec<usize>>,
+    // Every section's edges, in section order
+    edges: Vec<IndexedEdge>,
+    // The cells each edge is listed in
+    edge_ranges: Vec<CellRange>,
+}
+
+// A rectangle of cells, inclusive at both ends
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct CellRange {
+    first_column: usize,
+    first_row: usize,
+    last_column: usize,
+    last_row: usize,
+}
+
+impl CellRange {
+    // Row by row
+    fn cells(self) -> impl Iterator<Item = (usize, usize)> {
+        (self.first_row..=self.last_row)
+            .flat_map(move |row| (self.first_column..=self.last_column).map(move |column| (column, row)))
+    }
+
+    // The first cell `cells` gives that's in both ranges, if they overlap at all
+    fn first_shared(&self, other: &CellRange) -> (usize, usize) {
+        (self.first_column.max(other.first_column), self.first_row.max(other.first_row))
+    }
+}
+
+impl SpatialIndex {
+    pub fn new(sections: &[Box<dyn TrackSection + Send + Sync>]) -> SpatialIndex {
+        SpatialIndex::with_max_cells(sections, MAX_CELLS)
+    }
+
+    // A single cell makes every query look at the whole track, as if there were no index
+    pub fn with_max_cells(sections: &[Box<dyn TrackSection + Send + Sync>], max_cells: usize) -> SpatialIndex {
+        let max_cells = max_cells.max(1);
+        let section_edges = sections.iter().map(|section| section.edges()).collect::<Vec<_>>();
+        let edges = section_edges
+            .iter()
+            .enumerate()
+            .flat_map(|(section, edges)| edges.iter().map(move |edge| IndexedEdge { section, edge: *edge }))
+            .collect::<Vec<_>>();
+        let section_bounds = sections.iter().map(|section| section.bounds()).collect::<Vec<_>>();
+        let Some((min, max)) = bounding_box(section_bounds.iter().flatten().flat_map(|(min, max)| [*min, *max])) else {
+            return SpatialIndex::default();
+        };
+
+        let size = max - min;
+        let area = (size.x * size.y).max(f32::EPSILON);
+        let cells = (edges.len() / EDGES_PER_CELL).clamp(1, max_cells);
+        let mut cell_m = (area / cells as f32).sqrt().max(f32::EPSILON);
+        // Long thin tracks would otherwise get cells far narrower than the track
+        while (size.x / cell_m).ceil().max(1.0) * (size.y / cell_m).ceil().max(1.0) > max_cells as f32 {
+            cell_m *= 2.0;
+        }
+        let columns = (size.x / cell_m).ceil().max(1.0) as usize;
+        let rows = (size.y / cell_m).ceil().max(1.0) as usize;
+        let mut index = SpatialIndex {
+            origin: min,
+            cell_m,
+            columns,
+            rows,
+            section_cells: vec![Vec::new(); columns * rows],
+            edge_cells: vec![Vec::new(); columns * rows],
+            edges: Vec::new(),
+            edge_ranges: Vec::with_capacity(edges.len()),
+        };
+
+        for (section, bounds) in section_bounds.iter().enumerate() {
+            if let Some((min, max)) = bounds {
+                for (column, row) in index.cell_range(*min, *max).cells() {
+                    index.section_cells[row * columns + column].push(section);
+                }
+            }
+        }
+        for (id, edge) in edges.iter().enumerate() {
+            let (min, max) = bounding_box([edge.edge.0, edge.edge.1]).expect("an edge has two ends");
+            let range = index.cell_range(min, max);
+            for (column, row) in range.cells() {
+                index.edge_cells[row * columns + column].push(id);
+            }
+            index.edge_ranges.push(range);
+        }
+        index.edges = edges;
+        index
+    }
+
+    // Sections that might contain `point`. Nothing outside the track's bounding box is on it
+    pub fn sections_at(&self, point: &Vec2d) -> &[usize] {
+        let offset = *point - self.origin;
+        if self.section_cells.is_empty()
+            || offset.x < 0.0
+            || offset.y < 0.0
+            || offset.x > self.columns as f32 * self.cell_m
+            || offset.y > self.rows as f32 * self.cell_m
+        {
+            return &[];
+        }
+        &self.section_cells[self.cell_of(*point)]
+    }
+
+    // Edges that might cross the box from `min` to `max`, each once. They come cell by cell and
+    // in section order within a cell, so the order only depends on the box
+    pub fn edges_near(&self, min: Vec2d, max: Vec2d) -> impl Iterator<Item = &IndexedEdge> + '_ {
+        let range = (!self.edge_cells.is_empty()).then(|| self.cell_range(min, max));
+        range.into_iter().flat_map(move |range| {
+            range.cells().flat_map(move |(column, row)| {
+                self.edge_cells[row * self.columns + column]
+                    .iter()
+                    // An edge listed in several of the cells is only given in the first of them
+                    .filter(move |id| self.edge_ranges[**id].first_shared(&range) == (column, row))
+                    .map(|id| &self.edges[*id])
+            })
+        })
+    }
+
+    fn cell_of(&self, point: Vec2d) -> usize {
+        let (column, row) = self.column_row(point);
+        row * self.columns + column
+    }
+
+    // Clamped to the grid, so points just outside land in the nearest cell
+    fn column_row(&self, point: Vec2d) -> (usize, usize) {
+        let offset = point - self.origin;
+        let column = ((offset.x / self.cell_m).floor().max(0.0) as usize).min(self.columns - 1);
+        let row = ((offset.y / self.cell_m).floor().max(0.0) as usize).min(self.rows - 1);
+        (column, row)
+    }
+
+    // Only for an index with cells
+    fn cell_range(&self, min: Vec2d, max: Vec2d) -> CellRange {
+        let (first_column, first_row) = self.column_row(min);
+        let (last_column, last_row) = self.column_row(max);
+        CellRange {
+            first_column,
+            first_row,
+            last_column,
+            last_row,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default_tracks::PRESETS;
+
+    #[test]
+    fn edges_near_a_box_come_once_each() {
+        for preset in PRESETS {
+            let track = preset.build();
+            let index = track.spatial_index();
+            let every_edge = track
+                .sections()
+                .iter()
+                .enumerate()
+                .flat_map(|(section, piece)| piece.edges().into_iter().map(move |edge| IndexedEdge { section, edge }))
+                .collect::<Vec<_>>();
+            for i in 0..50 {
+                let min = Vec2d::new(-120.0 + 7.0 * i as f32, -20.0 + 6.0 * i as f32);
+                let max = min + Vec2d::new(5.0 + 3.0 * (i % 7) as f32, 40.0 - 5.0 * (i % 5) as f32);
+                let mut near = index.edges_near(min, max).copied().collect::<Vec<_>>();
+                let before = near.len();
+                let key = |edge: &IndexedEdge| (edge.section, [edge.edge.0.x, edge.edge.0.y, edge.edge.1.x, edge.edge.1.y].map(f32::to_bits));
+                near.sort_by_key(key);
+                near.dedup();
+                assert_eq!(near.len(), before, "{} gave an edge twice", preset.name);
+                // Anything whose bounding box meets the box has to be there
+                for edge in &every_edge {
+                    let (edge_min, edge_max) = bounding_box([edge.edge.0, edge.edge.1]).unwrap();
+                    if edge_min.x <= max.x && edge_max.x >= min.x && edge_min.y <= max.y && edge_max.y >= min.y {
+                        assert!(near.contains(edge), "{} left out {:?}", preset.name, edge);
+                    }
+                }
+            }
+        }
+    }
+}
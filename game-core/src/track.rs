@@ -1,12 +1,36 @@
-use crate::car::TerminationCondition;
-use crate::coordinates::{Boundary, Vec2d};
+use std::any::Any;
+use std::f32::consts::{PI, TAU};
+use std::sync::OnceLock;
 
-pub trait TrackSection {
+use serde::{Deserialize, Serialize};
+
+use crate::car::{TerminationCondition, CAR_LENGTH_M, CAR_WIDTH_M};
+use crate::centerline::CenterlineSection;
+use crate::collision::{CollisionResponse, OrientedRect};
+use crate::coordinates::{bounding_box, Vec2d};
+use crate::gate::Gate;
+use crate::racing_line::RacingLine;
+use crate::spatial_index::SpatialIndex;
+
+// Sections are `Any` so the track file format can tell which kind each one is, see `track_file`
+pub trait TrackSection: Any {
     fn is_within(&self, pos: &Vec2d) -> bool;
 
     fn edges(&self) -> Vec<(Vec2d, Vec2d)>;
+
+    // A box holding every point `is_within` accepts. Sections whose insides bulge past their
+    // edges have to make it bigger
+    fn bounds(&self) -> Option<(Vec2d, Vec2d)> {
+        bounding_box(self.edges().into_iter().flat_map(|(a, b)| [a, b]))
+    }
+
+    // Sections laid along a centerline give the racing line its shape between gates
+    fn centerline(&self) -> Option<&CenterlineSection> {
+        None
+    }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ParallelRectSection {
     pub left_x: f32,
     pub right_x: f32,
@@ -47,19 +71,460 @@ impl TrackSection for ParallelRectSection {
     }
 }
 
+// Any simple polygon, convex or concave. Vertices can be given in either winding order
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PolygonSection {
+    pub vertices: Vec<Vec2d>,
+}
+
+impl TrackSection for PolygonSection {
+    fn is_within(&self, pos: &Vec2d) -> bool {
+        // Even-odd rule: count the edges crossed by a ray heading off to +x
+        let mut inside = false;
+        for (a, b) in self.edges() {
+            if (a.y > pos.y) != (b.y > pos.y) {
+                let x_cross = a.x + (pos.y - a.y) * (b.x - a.x) / (b.y - a.y);
+                if pos.x < x_cross {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    fn edges(&self) -> Vec<(Vec2d, Vec2d)> {
+        let n = self.vertices.len();
+        (0..n).map(|i| (self.vertices[i], self.vertices[(i + 1) % n])).collect()
+    }
+}
+
+// Maximum angle covered by a single straight edge when drawing an arc
+const ARC_EDGE_RADIANS: f32 = PI / 32.0;
+
+// A curved piece of road between two concentric circles. Angles use the same convention as the
+// car: 0 points up and angles increase clockwise. A negative span sweeps anticlockwise
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ArcSection {
+    pub centre: Vec2d,
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    pub start_radians: f32,
+    pub span_radians: f32,
+}
+
+impl ArcSection {
+    pub fn point_at(&self, radius: f32, radians: f32) -> Vec2d {
+        self.centre + Vec2d::from_heading(radians) * radius
+    }
+
+    fn covers_angle(&self, radians: f32) -> bool {
+        if self.span_radians.abs() >= TAU {
+            return true;
+        }
+        let swept = (radians - self.start_radians) * self.span_radians.signum();
+        swept.rem_euclid(TAU) < self.span_radians.abs()
+    }
+}
+
+impl TrackSection for ArcSection {
+    fn is_within(&self, pos: &Vec2d) -> bool {
+        let offset = *pos - self.centre;
+        let radius = offset.length();
+        radius > self.inner_radius && radius < self.outer_radius && self.covers_angle(offset.heading())
+    }
+
+    fn edges(&self) -> Vec<(Vec2d, Vec2d)> {
+        let segments = (self.span_radians.abs() / ARC_EDGE_RADIANS).ceil().max(1.0) as usize;
+        let step = self.span_radians / segments as f32;
+        let mut vec = Vec::with_capacity(2 * segments + 2);
+        for radius in [self.inner_radius, self.outer_radius] {
+            if radius <= 0.0 {
+                continue;
+            }
+            for i in 0..segments {
+                let from = self.start_radians + step * i as f32;
+                vec.push((self.point_at(radius, from), self.point_at(radius, from + step)));
+            }
+        }
+        if self.span_radians.abs() < TAU {
+            let end_radians = self.start_radians + self.span_radians;
+            for radians in [self.start_radians, end_radians] {
+                vec.push((
+                    self.point_at(self.inner_radius, radians),
+                    self.point_at(self.outer_radius, radians),
+                ));
+            }
+        }
+        vec
+    }
+
+    // The edges cut across the curves, which bow out by up to this much between them
+    fn bounds(&self) -> Option<(Vec2d, Vec2d)> {
+        let bulge = self.outer_radius * (1.0 - (ARC_EDGE_RADIANS / 2.0).cos());
+        let pad = Vec2d::new(bulge, bulge);
+        let (min, max) = bounding_box(self.edges().into_iter().flat_map(|(a, b)| [a, b]))?;
+        Some((min - pad, max + pad))
+    }
+}
+
+// A rectangle of road centred on `centre`, with its length running along `direction_radians`
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RotatedRectSection {
+    pub centre: Vec2d,
+    pub width: f32,
+    pub length: f32,
+    pub direction_radians: f32,
+}
+
+impl RotatedRectSection {
+    pub fn corners(&self) -> [Vec2d; 4] {
+        let forward = Vec2d::from_heading(self.direction_radians) * (self.length / 2.0);
+        let right = Vec2d::from_heading(self.direction_radians).perp_right() * (self.width / 2.0);
+        [
+            self.centre - forward - right,
+            self.centre + forward - right,
+            self.centre + forward + right,
+            self.centre - forward + right,
+        ]
+    }
+}
+
+impl TrackSection for RotatedRectSection {
+    fn is_within(&self, pos: &Vec2d) -> bool {
+        let forward = Vec2d::from_heading(self.direction_radians);
+        let offset = *pos - self.centre;
+        offset.dot(forward).abs() < self.length / 2.0 && offset.dot(forward.perp_right()).abs() < self.width / 2.0
+    }
+
+    fn edges(&self) -> Vec<(Vec2d, Vec2d)> {
+        let corners = self.corners();
+        (0..4).map(|i| (corners[i], corners[(i + 1) % 4])).collect()
+    }
+}
+
+// Size and spacing of the grid laid out for tracks that don't list their own slots. There should
+// be room for at least GRID_SLOTS cars
+pub const GRID_SLOTS: usize = 8;
+pub const GRID_ROW_SPACING_M: f32 = 8.0;
+pub const GRID_COLUMN_SPACING_M: f32 = 5.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GridSlot {
+    pub pos: Vec2d,
+    pub direction_radians: f32,
+}
+
+impl GridSlot {
+    // Slot `index` of a two column grid behind `pole`, the second column to its right and half a
+    // row further back
+    pub fn staggered(pole: GridSlot, index: usize) -> GridSlot {
+        let forward = Vec2d::from_heading(pole.direction_radians);
+        let (row, column) = ((index / 2) as f32, (index % 2) as f32);
+        let back = (row + column / 2.0) * GRID_ROW_SPACING_M;
+        GridSlot {
+            pos: pole.pos - forward * back + forward.perp_right() * (column * GRID_COLUMN_SPACING_M),
+            direction_radians: pole.direction_radians,
+        }
+    }
+
+    // Where a car waiting in the slot is
+    pub fn body(&self) -> OrientedRect {
+        OrientedRect {
+            centre: self.pos,
+            width: CAR_WIDTH_M,
+            length: CAR_LENGTH_M,
+            direction_radians: self.direction_radians,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackMetadata {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
 pub struct Track {
-    pub start: Vec2d,
-    pub finish_line: Boundary,
-    pub sections: Vec<Box<dyn TrackSection + Send + Sync>>,
+    pub metadata: TrackMetadata,
+    // The start, gates and laps are only changed through setters, which throw away the racing
+    // line measured along them
+    start: Vec2d,
+    // Heading of a car on the start line, using the same convention as `Car::direction_radians`
+    start_direction_radians: f32,
+    finish_line: Gate,
+    // Gates to pass through in order on every lap before the finish line counts
+    checkpoints: Vec<Gate>,
+    laps: u32,
+    // Starting positions, pole first. Without any, cars line up in a staggered grid from `start`
+    pub grid: Vec<GridSlot>,
+    // Only changed through `Track::set_sections`, which throws away what was built from them
+    sections: Vec<Box<dyn TrackSection + Send + Sync>>,
     pub termination_condition: TerminationCondition,
+    pub collision_response: CollisionResponse,
+    // Built from the start and gates the first time a car's progress is measured, see
+    // `Track::racing_line`
+    racing_line: OnceLock<RacingLine>,
+    // Built from the sections the first time the track is queried
+    spatial_index: OnceLock<SpatialIndex>,
 }
 
 impl Track {
+    // A single lap from `start` to the finish line with no checkpoints, lining cars up on the
+    // default grid. The `with_` methods fill in the rest
+    pub fn new(
+        metadata: TrackMetadata,
+        start: Vec2d,
+        start_direction_radians: f32,
+        finish_line: Gate,
+        sections: Vec<Box<dyn TrackSection + Send + Sync>>,
+        termination_condition: TerminationCondition,
+    ) -> Track {
+        Track {
+            metadata,
+            start,
+            start_direction_radians,
+            finish_line,
+            checkpoints: Vec::new(),
+            laps: 1,
+            grid: Vec::new(),
+            sections,
+            termination_condition,
+            collision_response: Default::default(),
+            racing_line: Default::default(),
+            spatial_index: Default::default(),
+        }
+    }
+
+    pub fn with_checkpoints(mut self, checkpoints: Vec<Gate>) -> Track {
+        self.set_checkpoints(checkpoints);
+        self
+    }
+
+    pub fn with_laps(mut self, laps: u32) -> Track {
+        self.set_laps(laps);
+        self
+    }
+
+    pub fn with_grid(mut self, grid: Vec<GridSlot>) -> Track {
+        self.grid = grid;
+        self
+    }
+
+    pub fn with_collision_response(mut self, collision_response: CollisionResponse) -> Track {
+        self.collision_response = collision_response;
+        self
+    }
+
+    pub fn start(&self) -> Vec2d {
+        self.start
+    }
+
+    pub fn start_direction_radians(&self) -> f32 {
+        self.start_direction_radians
+    }
+
+    pub fn set_start(&mut self, start: Vec2d, direction_radians: f32) {
+        self.start = start;
+        self.start_direction_radians = direction_radians;
+        self.racing_line = OnceLock::new();
+    }
+
+    pub fn finish_line(&self) -> &Gate {
+        &self.finish_line
+    }
+
+    pub fn set_finish_line(&mut self, finish_line: Gate) {
+        self.finish_line = finish_line;
+        self.racing_line = OnceLock::new();
+    }
+
+    pub fn checkpoints(&self) -> &[Gate] {
+        &self.checkpoints
+    }
+
+    pub fn set_checkpoints(&mut self, checkpoints: Vec<Gate>) {
+        self.checkpoints = checkpoints;
+        self.racing_line = OnceLock::new();
+    }
+
+    pub fn laps(&self) -> u32 {
+        self.laps
+    }
+
+    pub fn set_laps(&mut self, laps: u32) {
+        self.laps = laps;
+        self.racing_line = OnceLock::new();
+    }
+
+    pub fn sections(&self) -> &[Box<dyn TrackSection + Send + Sync>] {
+        &self.sections
+    }
+
+    pub fn set_sections(&mut self, sections: Vec<Box<dyn TrackSection + Send + Sync>>) {
+        self.sections = sections;
+        self.racing_line = OnceLock::new();
+        self.spatial_index = OnceLock::new();
+    }
+
+    pub fn racing_line(&self) -> &RacingLine {
+        self.racing_line.get_or_init(|| RacingLine::new(self))
+    }
+
+    pub(crate) fn spatial_index(&self) -> &SpatialIndex {
+        self.spatial_index.get_or_init(|| SpatialIndex::new(&self.sections))
+    }
+
     pub fn is_within_track(&self, point: &Vec2d) -> bool {
-        self.sections.iter().any(|section| section.is_within(point))
+        let index = self.spatial_index();
+        index.sections_at(point).iter().any(|section| self.sections[*section].is_within(point))
     }
 
-    pub fn is_finished(&self, point: &Vec2d) -> bool {
-        !self.finish_line.point_within(point)
+    // Each lap is split into sectors by the checkpoints, the last sector ending at the finish line
+    pub fn sectors_per_lap(&self) -> usize {
+        self.checkpoints.len() + 1
+    }
+
+    // None if there aren't that many slots
+    pub fn grid_slot(&self, index: usize) -> Option<GridSlot> {
+        if self.grid.is_empty() {
+            self.default_grid().nth(index)
+        } else {
+            self.grid.get(index).copied()
+        }
+    }
+
+    pub fn grid_size(&self) -> usize {
+        if self.grid.is_empty() {
+            self.default_grid().count()
+        } else {
+            self.grid.len()
+        }
+    }
+
+    // A staggered grid back from the start, leaving out slots that don't fit on the track. Only
+    // the first few rows are tried, so there's an end to it even on open ground
+    fn default_grid(&self) -> impl Iterator<Item = GridSlot> + '_ {
+        let pole = GridSlot {
+            pos: self.start,
+            direction_radians: self.start_direction_radians,
+        };
+        (0..4 * GRID_SLOTS)
+            .map(move |i| GridSlot::staggered(pole, i))
+            .filter(|slot| self.has_room_for(slot))
+    }
+
+    // Whether a car in the slot is entirely on the track
+    pub fn has_room_for(&self, slot: &GridSlot) -> bool {
+        slot.body().corners().iter().all(|corner| self.is_within_track(corner))
+    }
+
+    // The checkpoints in order and then the finish line
+    pub fn gate(&self, index: usize) -> &Gate {
+        self.checkpoints.get(index).unwrap_or(&self.finish_line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    use super::*;
+    use crate::gate::SegmentGate;
+
+    #[test]
+    fn concave_polygons_leave_out_their_notch() {
+        // A U opening upwards
+        let u = PolygonSection {
+            vertices: [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (7.0, 10.0), (7.0, 3.0), (3.0, 3.0), (3.0, 10.0), (0.0, 10.0)]
+                .iter()
+                .map(|(x, y)| Vec2d::new(*x, *y))
+                .collect(),
+        };
+        for inside in [(1.5, 8.0), (8.5, 8.0), (5.0, 1.5)] {
+            assert!(u.is_within(&Vec2d::new(inside.0, inside.1)), "{:?}", inside);
+        }
+        for outside in [(5.0, 6.0), (5.0, 11.0), (12.0, 5.0), (-1.0, 1.0)] {
+            assert!(!u.is_within(&Vec2d::new(outside.0, outside.1)), "{:?}", outside);
+        }
+    }
+
+    fn arc(start_radians: f32, span_radians: f32) -> ArcSection {
+        ArcSection {
+            centre: Vec2d::new(0.0, 0.0),
+            inner_radius: 10.0,
+            outer_radius: 20.0,
+            start_radians,
+            span_radians,
+        }
+    }
+
+    fn at(radius: f32, radians: f32) -> Vec2d {
+        Vec2d::from_heading(radians) * radius
+    }
+
+    #[test]
+    fn arcs_can_go_anticlockwise() {
+        // From straight up round to the left
+        let arc = arc(0.0, -FRAC_PI_2);
+        assert!(arc.is_within(&at(15.0, -FRAC_PI_4)));
+        assert!(arc.is_within(&at(15.0, -1.5)));
+        assert!(!arc.is_within(&at(15.0, FRAC_PI_4)));
+        assert!(!arc.is_within(&at(15.0, -1.7)));
+        assert!(!arc.is_within(&at(5.0, -FRAC_PI_4)));
+        assert!(!arc.is_within(&at(25.0, -FRAC_PI_4)));
+    }
+
+    #[test]
+    fn arcs_can_span_straight_down() {
+        // From down and to the right, past pi, to down and to the left
+        let arc = arc(3.0 * FRAC_PI_4, FRAC_PI_2);
+        assert!(arc.is_within(&at(15.0, PI)));
+        assert!(arc.is_within(&at(15.0, 0.8 * PI)));
+        assert!(arc.is_within(&at(15.0, -0.8 * PI)));
+        assert!(!arc.is_within(&at(15.0, 0.6 * PI)));
+        assert!(!arc.is_within(&at(15.0, -0.6 * PI)));
+        assert!(!arc.is_within(&at(15.0, 0.0)));
+    }
+
+    #[test]
+    fn rotated_rects_are_tested_along_their_own_axes() {
+        let rect = RotatedRectSection {
+            centre: Vec2d::new(0.0, 0.0),
+            width: 4.0,
+            length: 10.0,
+            direction_radians: FRAC_PI_4,
+        };
+        for corner in rect.corners() {
+            assert!(rect.is_within(&(corner * 0.95)), "{:?}", corner);
+            assert!(!rect.is_within(&(corner * 1.05)), "{:?}", corner);
+        }
+        // Inside the box the rect would fill if it weren't turned
+        assert!(!rect.is_within(&Vec2d::new(4.5, -2.0)));
+    }
+
+    #[test]
+    fn moving_the_gates_measures_the_racing_line_again() {
+        let finish_at = |y: f32| SegmentGate::across(Vec2d::new(0.0, y), 20.0, 0.0).into();
+        let mut track = Track::new(
+            TrackMetadata::default(),
+            Vec2d::new(0.0, 0.0),
+            0.0,
+            finish_at(100.0),
+            vec![Box::new(ParallelRectSection {
+                left_x: -10.0,
+                right_x: 10.0,
+                top_y: 300.0,
+                bottom_y: -10.0,
+            })],
+            TerminationCondition::Seconds(100.0),
+        );
+        assert!((track.race_length() - 100.0).abs() < 1.0);
+
+        track.set_finish_line(finish_at(200.0));
+        assert!((track.race_length() - 200.0).abs() < 1.0);
+        track.set_checkpoints(vec![finish_at(50.0)]);
+        track.set_start(Vec2d::new(0.0, 20.0), 0.0);
+        assert!((track.race_length() - 180.0).abs() < 1.0);
     }
 }
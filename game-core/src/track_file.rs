@@ -0,0 +1,401 @@
+use std::any::Any;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::car::TerminationCondition;
+use crate::centerline::{Centerline, CenterlineSection};
+use crate::collision::CollisionResponse;
+use crate::coordinates::Vec2d;
+use crate::gate::Gate;
+use crate::track::{
+    ArcSection, GridSlot, ParallelRectSection, PolygonSection, RotatedRectSection, Track, TrackMetadata, TrackSection,
+};
+
+// Bump this whenever the layout of `TrackFile` changes in a way older readers can't handle
+pub const TRACK_FILE_VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TrackFormat {
+    Ron,
+    Json,
+    Toml,
+}
+
+impl TrackFormat {
+    pub fn from_path(path: &Path) -> Option<TrackFormat> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "ron" => Some(TrackFormat::Ron),
+            "json" => Some(TrackFormat::Json),
+            "toml" => Some(TrackFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TrackFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TrackFormat::Ron => "RON",
+            TrackFormat::Json => "JSON",
+            TrackFormat::Toml => "TOML",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug)]
+pub enum TrackFileError {
+    Io { path: PathBuf, source: std::io::Error },
+    UnknownFormat(PathBuf),
+    Parse { format: TrackFormat, message: String },
+    Serialize { format: TrackFormat, message: String },
+    UnsupportedVersion(u32),
+    Invalid(String),
+}
+
+impl fmt::Display for TrackFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackFileError::Io { path, source } => write!(f, "could not access {}: {}", path.display(), source),
+            TrackFileError::UnknownFormat(path) => write!(
+                f,
+                "can't tell the track format of {}, expected a .ron, .json or .toml extension",
+                path.display()
+            ),
+            TrackFileError::Parse { format, message } => write!(f, "malformed {} track file: {}", format, message),
+            TrackFileError::Serialize { format, message } => write!(f, "could not write {} track file: {}", format, message),
+            TrackFileError::UnsupportedVersion(version) => write!(
+                f,
+                "track file version {} is not supported, this build reads up to version {}",
+                version, TRACK_FILE_VERSION
+            ),
+            TrackFileError::Invalid(reason) => write!(f, "invalid track: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for TrackFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TrackFileError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SectionSpec {
+    ParallelRect(ParallelRectSection),
+    Polygon(PolygonSection),
+    Arc(ArcSection),
+    RotatedRect(RotatedRectSection),
+    Centerline(Centerline),
+}
+
+impl From<&SectionSpec> for Box<dyn TrackSection + Send + Sync> {
+    fn from(spec: &SectionSpec) -> Self {
+        match spec {
+            SectionSpec::ParallelRect(section) => Box::new(*section),
+            SectionSpec::Polygon(section) => Box::new(section.clone()),
+            SectionSpec::Arc(section) => Box::new(*section),
+            SectionSpec::RotatedRect(section) => Box::new(*section),
+            SectionSpec::Centerline(centerline) => Box::new(CenterlineSection::new(centerline.clone())),
+        }
+    }
+}
+
+// Fails for section types defined outside this crate, which the format has no way to describe
+impl TryFrom<&dyn TrackSection> for SectionSpec {
+    type Error = ();
+
+    fn try_from(section: &dyn TrackSection) -> Result<SectionSpec, ()> {
+        let section = section as &dyn Any;
+        if let Some(rect) = section.downcast_ref::<ParallelRectSection>() {
+            Ok(SectionSpec::ParallelRect(*rect))
+        } else if let Some(polygon) = section.downcast_ref::<PolygonSection>() {
+            Ok(SectionSpec::Polygon(polygon.clone()))
+        } else if let Some(arc) = section.downcast_ref::<ArcSection>() {
+            Ok(SectionSpec::Arc(*arc))
+        } else if let Some(rect) = section.downcast_ref::<RotatedRectSection>() {
+            Ok(SectionSpec::RotatedRect(*rect))
+        } else if let Some(centerline) = section.downcast_ref::<CenterlineSection>() {
+            Ok(SectionSpec::Centerline(centerline.centerline.clone()))
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl SectionSpec {
+    fn check(&self) -> Result<(), String> {
+        match self {
+            SectionSpec::ParallelRect(rect) => {
+                if rect.left_x < rect.right_x && rect.bottom_y < rect.top_y {
+                    Ok(())
+                } else {
+                    Err(format!("rectangle section {:?} has its sides the wrong way round", rect))
+                }
+            }
+            SectionSpec::Polygon(polygon) => {
+                if polygon.vertices.len() >= 3 {
+                    Ok(())
+                } else {
+                    Err(format!("polygon section needs at least 3 vertices, got {}", polygon.vertices.len()))
+                }
+            }
+            SectionSpec::Arc(arc) => {
+                if !(arc.inner_radius >= 0.0 && arc.inner_radius < arc.outer_radius) {
+                    Err(format!(
+                        "arc section needs 0 <= inner_radius < outer_radius, got {} and {}",
+                        arc.inner_radius, arc.outer_radius
+                    ))
+                } else if arc.span_radians == 0.0 || !arc.span_radians.is_finite() {
+                    Err(format!("arc section has an invalid span of {} radians", arc.span_radians))
+                } else {
+                    Ok(())
+                }
+            }
+            SectionSpec::RotatedRect(rect) => {
+                if rect.width > 0.0 && rect.length > 0.0 {
+                    Ok(())
+                } else {
+                    Err(format!("rotated rectangle needs a positive width and length, got {} x {}", rect.width, rect.length))
+                }
+            }
+            SectionSpec::Centerline(centerline) => centerline.check(),
+        }
+    }
+}
+
+fn one_lap() -> u32 {
+    1
+}
+
+// The on-disk representation of a `Track`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrackFile {
+    pub version: u32,
+    #[serde(default)]
+    pub metadata: TrackMetadata,
+    pub start: Vec2d,
+    #[serde(default)]
+    pub start_direction_radians: f32,
+    pub finish_line: Gate,
+    #[serde(default)]
+    pub checkpoints: Vec<Gate>,
+    #[serde(default = "one_lap")]
+    pub laps: u32,
+    #[serde(default)]
+    pub grid: Vec<GridSlot>,
+    pub termination_condition: TerminationCondition,
+    #[serde(default)]
+    pub collision_response: CollisionResponse,
+    pub sections: Vec<SectionSpec>,
+}
+
+impl TrackFile {
+    pub fn from_track(track: &Track) -> Result<TrackFile, TrackFileError> {
+        let sections = track
+            .sections()
+            .iter()
+            .enumerate()
+            .map(|(i, section)| {
+                SectionSpec::try_from(&**section as &dyn TrackSection)
+                    .map_err(|_| TrackFileError::Invalid(format!("section {} is of a type track files can't hold", i)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TrackFile {
+            version: TRACK_FILE_VERSION,
+            metadata: track.metadata.clone(),
+            start: track.start(),
+            start_direction_radians: track.start_direction_radians(),
+            finish_line: *track.finish_line(),
+            checkpoints: track.checkpoints().to_vec(),
+            laps: track.laps(),
+            grid: track.grid.clone(),
+            termination_condition: track.termination_condition,
+            collision_response: track.collision_response,
+            sections,
+        })
+    }
+
+    pub fn into_track(self) -> Result<Track, TrackFileError> {
+        if self.version > TRACK_FILE_VERSION {
+            return Err(TrackFileError::UnsupportedVersion(self.version));
+        }
+        if self.sections.is_empty() {
+            return Err(TrackFileError::Invalid("the track has no sections".to_string()));
+        }
+        if self.laps == 0 {
+            return Err(TrackFileError::Invalid("the track needs at least one lap".to_string()));
+        }
+        self.collision_response.check().map_err(TrackFileError::Invalid)?;
+        self.finish_line
+            .check()
+            .map_err(|reason| TrackFileError::Invalid(format!("finish line: {}", reason)))?;
+        for (i, checkpoint) in self.checkpoints.iter().enumerate() {
+            checkpoint
+                .check()
+                .map_err(|reason| TrackFileError::Invalid(format!("checkpoint {}: {}", i, reason)))?;
+        }
+        for (i, section) in self.sections.iter().enumerate() {
+            section
+                .check()
+                .map_err(|reason| TrackFileError::Invalid(format!("section {}: {}", i, reason)))?;
+        }
+        Ok(Track::new(
+            self.metadata,
+            self.start,
+            self.start_direction_radians,
+            self.finish_line,
+            self.sections.iter().map(Into::into).collect(),
+            self.termination_condition,
+        )
+        .with_checkpoints(self.checkpoints)
+        .with_laps(self.laps)
+        .with_grid(self.grid)
+        .with_collision_response(self.collision_response))
+    }
+
+    pub fn parse(contents: &str, format: TrackFormat) -> Result<TrackFile, TrackFileError> {
+        let parse_err = |message: String| TrackFileError::Parse { format, message };
+        match format {
+            TrackFormat::Ron => ron::from_str(contents).map_err(|e| parse_err(e.to_string())),
+            TrackFormat::Json => serde_json::from_str(contents).map_err(|e| parse_err(e.to_string())),
+            TrackFormat::Toml => toml::from_str(contents).map_err(|e| parse_err(e.to_string())),
+        }
+    }
+
+    pub fn serialize(&self, format: TrackFormat) -> Result<String, TrackFileError> {
+        let serialize_err = |message: String| TrackFileError::Serialize { format, message };
+        match format {
+            TrackFormat::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .map_err(|e| serialize_err(e.to_string())),
+            TrackFormat::Json => serde_json::to_string_pretty(self).map_err(|e| serialize_err(e.to_string())),
+            TrackFormat::Toml => toml::to_string_pretty(self).map_err(|e| serialize_err(e.to_string())),
+        }
+    }
+}
+
+impl Track {
+    // The format is picked from the file extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Track, TrackFileError> {
+        let path = path.as_ref();
+        let format = TrackFormat::from_path(path).ok_or_else(|| TrackFileError::UnknownFormat(path.to_path_buf()))?;
+        let contents = fs::read_to_string(path).map_err(|source| TrackFileError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        TrackFile::parse(&contents, format)?.into_track()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TrackFileError> {
+        let path = path.as_ref();
+        let format = TrackFormat::from_path(path).ok_or_else(|| TrackFileError::UnknownFormat(path.to_path_buf()))?;
+        let contents = TrackFile::from_track(self)?.serialize(format)?;
+        fs::write(path, contents).map_err(|source| TrackFileError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::centerline::ControlPoint;
+    use crate::coordinates::{Boundary, LineType};
+    use crate::default_tracks;
+    use crate::gate::SegmentGate;
+
+    // One of every kind of section, gate and setting the format holds
+    fn every_feature() -> Track {
+        let sections: Vec<Box<dyn TrackSection + Send + Sync>> = vec![
+            Box::new(ParallelRectSection {
+                left_x: -10.0,
+                right_x: 10.0,
+                top_y: 100.0,
+                bottom_y: -10.0,
+            }),
+            Box::new(PolygonSection {
+                vertices: vec![Vec2d::new(-10.0, 100.0), Vec2d::new(10.0, 100.0), Vec2d::new(0.0, 120.0)],
+            }),
+            Box::new(ArcSection {
+                centre: Vec2d::new(30.0, 100.0),
+                inner_radius: 20.0,
+                outer_radius: 40.0,
+                start_radians: -1.5,
+                span_radians: 3.0,
+            }),
+            Box::new(RotatedRectSection {
+                centre: Vec2d::new(60.0, 50.0),
+                width: 20.0,
+                length: 80.0,
+                direction_radians: 0.3,
+            }),
+            Box::new(CenterlineSection::new(Centerline::catmull_rom(
+                vec![ControlPoint::new(60.0, 0.0, 20.0), ControlPoint::new(0.0, -20.0, 15.0), ControlPoint::new(-5.0, 0.0, 20.0)],
+                false,
+            ))),
+        ];
+        Track::new(
+            TrackMetadata {
+                name: "Everything".to_string(),
+                author: Some("Tests".to_string()),
+            },
+            Vec2d::new(0.0, 5.0),
+            0.1,
+            Gate::Line(Boundary {
+                line_type: LineType::Horizontal(90.0),
+                positive_inf_within: false,
+            }),
+            sections,
+            TerminationCondition::Ticks(900),
+        )
+        .with_checkpoints(vec![SegmentGate::across(Vec2d::new(60.0, 50.0), 20.0, PI).into()])
+        .with_laps(2)
+        .with_grid(vec![GridSlot {
+            pos: Vec2d::new(3.0, 0.0),
+            direction_radians: 0.1,
+        }])
+        .with_collision_response(CollisionResponse::Bounce { restitution: 0.5 })
+    }
+
+    #[test]
+    fn tracks_survive_saving_and_loading_in_every_format() {
+        let track = every_feature();
+        let dir = std::env::temp_dir().join(format!("track-file-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for extension in ["ron", "json", "toml"] {
+            let path = dir.join(format!("everything.{}", extension));
+            track.save(&path).unwrap();
+            let loaded = Track::load(&path).unwrap();
+            assert_eq!(TrackFile::from_track(&loaded).unwrap(), TrackFile::from_track(&track).unwrap(), "{}", extension);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let mut file = TrackFile::from_track(&every_feature()).unwrap();
+        file.version = TRACK_FILE_VERSION + 1;
+        assert!(matches!(file.into_track(), Err(TrackFileError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn centerline_sections_are_tagged_like_the_others() {
+        let track = default_tracks::preset("hairpin").expect("hairpin is a preset").build();
+        let file = TrackFile::from_track(&track).unwrap();
+        for format in [TrackFormat::Ron, TrackFormat::Json, TrackFormat::Toml] {
+            let contents = file.serialize(format).unwrap();
+            assert!(contents.contains("Centerline"), "{} file has no section type", format);
+            assert_eq!(TrackFile::parse(&contents, format).unwrap(), file);
+        }
+    }
+}
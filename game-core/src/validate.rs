@@ -0,0 +1,438 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::coordinates::{bounding_box, Vec2d};
+use crate::gate::Gate;
+use crate::track::{Track, GRID_SLOTS};
+
+// The track is checked on a grid of cells. Big tracks get bigger cells so checking stays quick,
+// but cells never get finer than MIN_CELL_M
+pub const MAX_CELLS_PER_SIDE: usize = 400;
+pub const MIN_CELL_M: f32 = 0.25;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+pub enum Severity {
+    // Worth a look, but cars can still race
+    Warning,
+    // Cars can't finish or can't start
+    Error,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum GateRef {
+    Checkpoint(usize),
+    Finish,
+}
+
+impl GateRef {
+    fn of(track: &Track, index: usize) -> GateRef {
+        if index < track.checkpoints().len() {
+            GateRef::Checkpoint(index)
+        } else {
+            GateRef::Finish
+        }
+    }
+}
+
+impl fmt::Display for GateRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GateRef::Checkpoint(index) => write!(f, "checkpoint {}", index),
+            GateRef::Finish => f.write_str("the finish line"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum Diagnostic {
+    StartOutsideTrack { start: Vec2d },
+    // Part of a car waiting in the slot would be off the track
+    GridSlotOutsideTrack { slot: usize },
+    // Fewer than GRID_SLOTS cars fit on the grid laid out for a track that doesn't list its own
+    SmallGrid { slots: usize },
+    // Too thin or small to hold a single cell of the check grid
+    DegenerateSection { section: usize },
+    // Sections grouped by the separate areas of road they make up
+    DisconnectedSections { groups: Vec<Vec<usize>> },
+    // Nowhere on the road that can be driven to from the start touches the gate
+    GateUnreachable { gate: GateRef },
+    // A half-plane gate the start is already on the far side of, so it can never be crossed
+    GateBehindStart { gate: GateRef },
+    OverlappingGates { first: GateRef, second: GateRef },
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Diagnostic::DegenerateSection { .. }
+            | Diagnostic::DisconnectedSections { .. }
+            | Diagnostic::OverlappingGates { .. }
+            | Diagnostic::SmallGrid { .. } => Severity::Warning,
+            Diagnostic::StartOutsideTrack { .. }
+            | Diagnostic::GridSlotOutsideTrack { .. }
+            | Diagnostic::GateUnreachable { .. }
+            | Diagnostic::GateBehindStart { .. } => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::StartOutsideTrack { start } => {
+                write!(f, "the start at ({}, {}) is outside every section", start.x, start.y)
+            }
+            Diagnostic::GridSlotOutsideTrack { slot } => write!(f, "grid slot {} isn't entirely on the track", slot),
+            Diagnostic::SmallGrid { slots } => {
+                write!(f, "only {} of {} grid slots fit on the track behind the start", slots, GRID_SLOTS)
+            }
+            Diagnostic::DegenerateSection { section } => write!(f, "section {} has no area to drive on", section),
+            Diagnostic::DisconnectedSections { groups } => {
+                write!(f, "the sections form {} separate pieces of road: {:?}", groups.len(), groups)
+            }
+            Diagnostic::GateUnreachable { gate } => write!(f, "{} can't be reached from the start", gate),
+            Diagnostic::GateBehindStart { gate } => write!(f, "the start is already past {}", gate),
+            Diagnostic::OverlappingGates { first, second } => write!(f, "{} and {} overlap", first, second),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Validation {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Validation {
+    // No errors, though there may be warnings
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.severity() == Severity::Error)
+    }
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            let severity = match diagnostic.severity() {
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            };
+            writeln!(f, "{}: {}", severity, diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+// The bounding box of the track cut into square cells, each labelled with the piece of road its
+// centre is on. 0 is off the track
+struct CellGrid {
+    origin: Vec2d,
+    cell_m: f32,
+    columns: usize,
+    rows: usize,
+    labels: Vec<u32>,
+}
+
+impl CellGrid {
+    fn new(track: &Track) -> CellGrid {
+        let bounds = track.sections().iter().filter_map(|section| section.bounds());
+        let Some((min, max)) = bounding_box(bounds.flat_map(|(min, max)| [min, max])) else {
+            return CellGrid {
+                origin: Vec2d::default(),
+                cell_m: MIN_CELL_M,
+                columns: 0,
+                rows: 0,
+                labels: Vec::new(),
+            };
+        };
+        let extent = (max - min).x.max((max - min).y);
+        let cell_m = (extent / MAX_CELLS_PER_SIDE as f32).max(MIN_CELL_M);
+        let columns = ((max.x - min.x) / cell_m).ceil() as usize + 1;
+        let rows = ((max.y - min.y) / cell_m).ceil() as usize + 1;
+        let mut grid = CellGrid {
+            origin: min,
+            cell_m,
+            columns,
+            rows,
+            labels: Vec::new(),
+        };
+        let on_track = (0..columns * rows)
+            .map(|cell| track.is_within_track(&grid.centre(cell)))
+            .collect::<Vec<_>>();
+        grid.labels = flood_fill(&on_track, columns, rows);
+        grid
+    }
+
+    fn centre(&self, cell: usize) -> Vec2d {
+        let (column, row) = (cell % self.columns, cell / self.columns);
+        self.origin + Vec2d::new((column as f32 + 0.5) * self.cell_m, (row as f32 + 0.5) * self.cell_m)
+    }
+
+    fn cell_at(&self, point: &Vec2d) -> Option<usize> {
+        let offset = *point - self.origin;
+        let (column, row) = ((offset.x / self.cell_m).floor(), (offset.y / self.cell_m).floor());
+        if column < 0.0 || row < 0.0 || column as usize >= self.columns || row as usize >= self.rows {
+            return None;
+        }
+        Some(row as usize * self.columns + column as usize)
+    }
+
+    fn label_at(&self, point: &Vec2d) -> u32 {
+        self.cell_at(point).map_or(0, |cell| self.labels[cell])
+    }
+
+    fn cells_labelled(&self, label: u32) -> impl Iterator<Item = usize> + '_ {
+        (0..self.labels.len()).filter(move |cell| self.labels[*cell] == label)
+    }
+
+    // The cells that can be driven to from `from` without touching any `blocked` cell
+    fn reachable_avoiding(&self, from: &Vec2d, blocked: impl Fn(usize) -> bool) -> Vec<usize> {
+        let on_track = (0..self.labels.len())
+            .map(|cell| self.labels[cell] != 0 && !blocked(cell))
+            .collect::<Vec<_>>();
+        let labels = flood_fill(&on_track, self.columns, self.rows);
+        let label = self.cell_at(from).map_or(0, |cell| labels[cell]);
+        (0..labels.len()).filter(|cell| label != 0 && labels[*cell] == label).collect()
+    }
+}
+
+// Labels each connected area of on-track cells, counting only cells that share a side
+fn flood_fill(on_track: &[bool], columns: usize, rows: usize) -> Vec<u32> {
+    let mut labels = vec![0; on_track.len()];
+    let mut next_label = 0;
+    let mut queue = VecDeque::new();
+    for seed in 0..on_track.len() {
+        if !on_track[seed] || labels[seed] != 0 {
+            continue;
+        }
+        next_label += 1;
+        labels[seed] = next_label;
+        queue.push_back(seed);
+        while let Some(cell) = queue.pop_front() {
+            let (column, row) = (cell % columns, cell / columns);
+            let neighbours = [
+                (column > 0).then(|| cell - 1),
+                (column + 1 < columns).then(|| cell + 1),
+                (row > 0).then(|| cell - columns),
+                (row + 1 < rows).then(|| cell + columns),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                if on_track[neighbour] && labels[neighbour] == 0 {
+                    labels[neighbour] = next_label;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+    labels
+}
+
+impl Track {
+    // Looks for mistakes that would stop cars starting, finishing or racing fairly. Nothing here
+    // stops a track loading
+    pub fn validate(&self) -> Validation {
+        let mut diagnostics = Vec::new();
+        let grid = CellGrid::new(self);
+
+        if !self.is_within_track(&self.start()) {
+            diagnostics.push(Diagnostic::StartOutsideTrack { start: self.start() });
+        }
+        for (slot, grid_slot) in self.grid.iter().enumerate() {
+            if !self.has_room_for(grid_slot) {
+                diagnostics.push(Diagnostic::GridSlotOutsideTrack { slot });
+            }
+        }
+        if self.grid.is_empty() && self.grid_size() < GRID_SLOTS {
+            diagnostics.push(Diagnostic::SmallGrid {
+                slots: self.grid_size(),
+            });
+        }
+
+        let mut pieces: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+        for (index, section) in self.sections().iter().enumerate() {
+            let labels = (0..grid.labels.len())
+                .filter(|cell| grid.labels[*cell] != 0 && section.is_within(&grid.centre(*cell)))
+                .map(|cell| grid.labels[cell])
+                .collect::<BTreeSet<_>>();
+            match labels.first() {
+                Some(label) => pieces.entry(*label).or_default().push(index),
+                None => diagnostics.push(Diagnostic::DegenerateSection { section: index }),
+            }
+        }
+        if pieces.len() > 1 {
+            diagnostics.push(Diagnostic::DisconnectedSections {
+                groups: pieces.into_values().collect(),
+            });
+        }
+
+        // A gate counts as touching a cell if it passes within half a cell of its centre
+        let start_label = grid.label_at(&self.start());
+        let reachable = grid.cells_labelled(start_label).filter(|_| start_label != 0).collect::<Vec<_>>();
+        let near = |gate: &Gate, cell: usize| gate.distance(&grid.centre(cell)) <= grid.cell_m / 2.0;
+        let near_by = |gate: &Gate, cell: usize| gate.distance(&grid.centre(cell)) <= grid.cell_m * 1.5;
+        let gates = (0..self.sectors_per_lap()).map(|i| (GateRef::of(self, i), self.gate(i))).collect::<Vec<_>>();
+        for (gate_ref, gate) in &gates {
+            if !reachable.iter().any(|cell| near(gate, *cell)) {
+                diagnostics.push(Diagnostic::GateUnreachable { gate: *gate_ref });
+                continue;
+            }
+            let behind = match gate {
+                Gate::Line(boundary) => !boundary.point_within(&self.start()),
+                // Driving up to the gate without going through it, only its far side can be got at
+                Gate::Segment(segment) => {
+                    let forward = Vec2d::from_heading(segment.direction_radians);
+                    let before = |cell: usize| (grid.centre(cell) - segment.start).dot(forward) < 0.0;
+                    let approach = grid.reachable_avoiding(&self.start(), |cell| near(gate, cell));
+                    !approach.is_empty() && !approach.iter().any(|cell| before(*cell) && near_by(gate, *cell))
+                }
+            };
+            if behind {
+                diagnostics.push(Diagnostic::GateBehindStart { gate: *gate_ref });
+            }
+        }
+        for (i, (first, first_gate)) in gates.iter().enumerate() {
+            for (second, second_gate) in &gates[i + 1..] {
+                if reachable.iter().any(|cell| near(first_gate, *cell) && near(second_gate, *cell)) {
+                    diagnostics.push(Diagnostic::OverlappingGates {
+                        first: *first,
+                        second: *second,
+                    });
+                }
+            }
+        }
+
+        Validation { diagnostics }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::TerminationCondition;
+    use crate::coordinates::{Boundary, LineType};
+    use crate::gate::SegmentGate;
+    use crate::track::{GridSlot, ParallelRectSection, TrackMetadata};
+
+    fn rect(left_x: f32, right_x: f32, bottom_y: f32, top_y: f32) -> ParallelRectSection {
+        ParallelRectSection {
+            left_x,
+            right_x,
+            top_y,
+            bottom_y,
+        }
+    }
+
+    fn across_at(x: f32, y: f32) -> Gate {
+        SegmentGate::across(Vec2d::new(x, y), 20.0, 0.0).into()
+    }
+
+    // A road 20 m wide running up from a start with room for a full grid behind it to a finish
+    // line 80 m ahead. `extra` sections go after it
+    fn road(extra: Vec<ParallelRectSection>) -> Track {
+        let sections = std::iter::once(rect(-10.0, 10.0, -40.0, 100.0))
+            .chain(extra)
+            .map(|section| Box::new(section) as Box<_>)
+            .collect();
+        Track::new(
+            TrackMetadata::default(),
+            Vec2d::new(0.0, 0.0),
+            0.0,
+            across_at(0.0, 80.0),
+            sections,
+            TerminationCondition::Seconds(100.0),
+        )
+    }
+
+    #[test]
+    fn a_plain_road_is_clean() {
+        assert_eq!(road(Vec::new()).validate().diagnostics, vec![]);
+    }
+
+    #[test]
+    fn starts_off_the_road_can_reach_nothing() {
+        // The grid is left where it was so only the start is wrong
+        let mut track = road(Vec::new()).with_grid(vec![GridSlot {
+            pos: Vec2d::new(0.0, 0.0),
+            direction_radians: 0.0,
+        }]);
+        let start = Vec2d::new(50.0, 0.0);
+        track.set_start(start, 0.0);
+        assert_eq!(
+            track.validate().diagnostics,
+            vec![
+                Diagnostic::StartOutsideTrack { start },
+                Diagnostic::GateUnreachable { gate: GateRef::Finish }
+            ]
+        );
+    }
+
+    #[test]
+    fn grid_slots_have_to_fit_on_the_road() {
+        let overhanging = GridSlot {
+            pos: Vec2d::new(9.5, 0.0),
+            direction_radians: 0.0,
+        };
+        let track = road(Vec::new()).with_grid(vec![overhanging]);
+        assert_eq!(track.validate().diagnostics, vec![Diagnostic::GridSlotOutsideTrack { slot: 0 }]);
+    }
+
+    #[test]
+    fn default_grids_want_room_for_every_slot() {
+        let mut track = road(Vec::new());
+        // Slots are staggered half a row apart, so the first three down to 8 m back are all that fit
+        track.set_sections(vec![Box::new(rect(-10.0, 10.0, -12.0, 100.0))]);
+        assert_eq!(track.validate().diagnostics, vec![Diagnostic::SmallGrid { slots: 3 }]);
+    }
+
+    #[test]
+    fn sections_too_thin_to_drive_on_are_degenerate() {
+        let track = road(vec![rect(0.0, 0.0, 0.0, 10.0)]);
+        assert_eq!(track.validate().diagnostics, vec![Diagnostic::DegenerateSection { section: 1 }]);
+    }
+
+    #[test]
+    fn sections_that_do_not_meet_are_disconnected() {
+        let track = road(vec![rect(30.0, 40.0, 0.0, 10.0)]);
+        assert_eq!(
+            track.validate().diagnostics,
+            vec![Diagnostic::DisconnectedSections {
+                groups: vec![vec![0], vec![1]]
+            }]
+        );
+    }
+
+    #[test]
+    fn finish_lines_off_the_road_are_unreachable() {
+        let mut track = road(Vec::new());
+        track.set_finish_line(across_at(50.0, 80.0));
+        assert_eq!(track.validate().diagnostics, vec![Diagnostic::GateUnreachable { gate: GateRef::Finish }]);
+    }
+
+    #[test]
+    fn half_plane_finish_lines_behind_the_start_can_not_be_crossed() {
+        let mut track = road(Vec::new());
+        // Crossed going up, but the start is already above it
+        track.set_finish_line(Gate::Line(Boundary {
+            line_type: LineType::Horizontal(-20.0),
+            positive_inf_within: false,
+        }));
+        assert_eq!(track.validate().diagnostics, vec![Diagnostic::GateBehindStart { gate: GateRef::Finish }]);
+    }
+
+    #[test]
+    fn gates_in_the_same_place_overlap() {
+        let track = road(Vec::new()).with_checkpoints(vec![across_at(0.0, 80.0)]);
+        assert_eq!(
+            track.validate().diagnostics,
+            vec![Diagnostic::OverlappingGates {
+                first: GateRef::Checkpoint(0),
+                second: GateRef::Finish
+            }]
+        );
+    }
+}